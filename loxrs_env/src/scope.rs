@@ -3,7 +3,7 @@ mod test;
 
 use core::fmt::{self, Display};
 use log::trace;
-use loxrs_types::{LoxErr, Result};
+use loxrs_types::{LoxErr, Result, Span};
 use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
 
 type Link<T> = Option<Rc<Scope<T>>>;
@@ -79,6 +79,7 @@ impl<T: Display + Clone> Scope<T> {
             Some(parent) => parent.as_ref().assign(name, val),
             None => Err(LoxErr::Undefined {
                 message: format!("attempted to assign to undefined var: {}", name),
+                span: Span::default(),
             }),
         }
     }
@@ -99,6 +100,7 @@ impl<T: Display + Clone> Scope<T> {
             .cloned()
             .ok_or(LoxErr::Undefined {
                 message: format!("variable undefined: {}", name),
+                span: Span::default(),
             })
     }
 
@@ -114,6 +116,7 @@ impl<T: Display + Clone> Scope<T> {
             .parent.as_ref()
             .ok_or::<LoxErr>(LoxErr::Internal {
                 message: "Error with ancestor depth! Expected to find some ancestor scope when resolving variable".to_owned(),
+                span: Span::default(),
             })
             .map(|el| el.as_ref())?;
         }
@@ -1,4 +1,4 @@
-use loxrs_types::{LoxErr, Result as LoxRes};
+use loxrs_types::{LoxErr, Result as LoxRes, Span};
 use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
 
 use super::{
@@ -55,6 +55,7 @@ impl Instance {
             Some(method) => Ok(Value::Func(Func::Lox(method.bind(Rc::clone(&instance))))),
             None => Err(LoxErr::Undefined {
                 message: format!("undefined property: {}", key),
+                span: Span::default(),
             }),
         }
     }
@@ -1,16 +1,75 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::io::{self, Write};
 use std::rc::Rc;
 use std::{cell::RefCell, fmt::Display};
 
 use super::{Expr, Value};
 use loxrs_env::Scope;
+use loxrs_types::Span;
 
 #[derive(Debug, Clone)]
 pub struct Interpreter {
     pub scope: Rc<Scope<Value>>,
     pub globals: Rc<Scope<Value>>,
     pub locals: RefCell<HashMap<Expr, usize>>,
+    pub out: Output,
+    /// the calls currently running, outermost first
+    pub frames: Vec<CallFrame>,
+    /// the frames the last runtime error unwound, innermost first
+    pub trace: Vec<TraceFrame>,
+}
+
+/// A call to a lox function, pushed and popped by `Func::call`
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub function: String,
+    /// where the caller called the function from
+    pub call_site: Span,
+}
+
+/// A frame of the stack trace a runtime error leaves behind
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// `None` for the script itself
+    pub function: Option<String>,
+    /// what the frame was running, i.e. the failing expression for the
+    /// innermost frame and a call for every other one
+    pub span: Span,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "[line {}] in {name}()", self.span.line),
+            None => write!(f, "[line {}] in script", self.span.line),
+        }
+    }
+}
+
+/// Where `print` writes to. Stdout, unless a test wants to capture the output
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<dyn Write>>);
+
+impl Output {
+    #[cfg(test)]
+    pub fn new(writer: Rc<RefCell<dyn Write>>) -> Self {
+        Self(writer)
+    }
+
+    pub fn stdout() -> Self {
+        Self(Rc::new(RefCell::new(io::stdout())))
+    }
+
+    pub fn writeln(&self, line: impl Display) -> io::Result<()> {
+        writeln!(self.0.borrow_mut(), "{line}")
+    }
+}
+
+impl Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Output")
+    }
 }
 
 impl Display for Interpreter {
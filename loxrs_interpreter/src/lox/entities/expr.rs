@@ -1,6 +1,7 @@
 use std::fmt::{self, Display};
 use std::hash::Hash;
 
+use loxrs_types::Span;
 use uuid::Uuid;
 
 use super::{stmt::StmtBlock, Literal, Token};
@@ -21,15 +22,18 @@ fn parenthesize<T: Display>(name: &str, expressions: Vec<&T>) -> String {
 pub struct Expr {
     id: Uuid,
     pub kind: ExprKind,
+    /// covers the whole expression, e.g. both operands of a binary one
+    pub span: Span,
 }
 
 impl Eq for Expr {}
 
 impl Expr {
-    pub fn new(kind: ExprKind) -> Self {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
         Self {
             id: Uuid::new_v4(),
             kind,
+            span,
         }
     }
 }
@@ -165,7 +169,7 @@ impl fmt::Display for ExprKind {
                 Self::Var(var) | Self::This(var) =>
                     var.literal.clone().map_or("None".to_string(), |t| format!(
                         "[<var> line: {}, col: {}, name: {}]",
-                        var.line, var.column, t
+                        var.span.line, var.span.column, t
                     )
                     .to_string()),
                 Self::Assign(assign) => format!(
@@ -6,7 +6,7 @@ use loxrs_env::Scope;
 use loxrs_types::Result;
 use std::cell::RefCell;
 use std::fmt::Result as fmt_result;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use std::rc::Rc;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -32,10 +32,22 @@ pub enum Func {
     Class(Rc<Class>),
 }
 
+impl Display for Func {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt_result {
+        match self {
+            Func::Lox(func) => write!(f, "<fn {}>", func.name()),
+            Func::Native(_) => write!(f, "<native fn>"),
+            Func::Class(class) => write!(f, "{}", class),
+        }
+    }
+}
+
 pub type FuncDefinition = fn(&mut Interpreter, Rc<Scope<Value>>) -> Result<Value>;
 
 #[derive(Clone)]
 pub struct Function {
+    /// the declared name, `None` for a function expression
+    pub name: Option<String>,
     pub def: ExprFunction,
     pub scope: Rc<Scope<Value>>,
     pub params: Vec<Token>,
@@ -48,7 +60,7 @@ impl Function {
     }
 
     pub fn name(&self) -> &str {
-        "<function>"
+        self.name.as_deref().unwrap_or("anonymous")
     }
 
     pub fn bind(&self, instance: Rc<RefCell<Instance>>) -> Self {
@@ -64,7 +76,8 @@ impl Function {
 
 impl PartialEq for Function {
     fn eq(&self, other: &Self) -> bool {
-        self.def == other.def
+        self.name == other.name
+            && self.def == other.def
             && self.params == other.params
             && self.is_initializer == other.is_initializer
     }
@@ -115,7 +128,7 @@ impl NativeFunction {
 
 impl PartialEq for NativeFunction {
     fn eq(&self, other: &Self) -> bool {
-        std::cmp::PartialEq::eq(&self.def, &other.def)
+        std::ptr::fn_addr_eq(self.def, other.def)
             && Rc::eq(&self.scope, &other.scope)
             && Vec::eq(&self.params, &other.params)
             && self.name == other.name
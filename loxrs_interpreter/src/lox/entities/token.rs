@@ -1,30 +1,29 @@
 use core::fmt;
 
-use loxrs_types::{LoxErr, Result};
+use loxrs_types::{LoxErr, Result, Span};
 
 use super::{val::Literal, TokenType};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub line: i32,
-    pub column: i32,
+    pub span: Span,
     pub literal: Option<Literal>,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, literal: Option<Literal>, line: i32, column: i32) -> Self {
+    pub fn new(token_type: TokenType, literal: Option<Literal>, span: Span) -> Self {
         Token {
             token_type,
             literal,
-            line,
-            column,
+            span,
         }
     }
 
     pub fn extract_identifier_str(&self) -> Result<&str> {
         let err = || LoxErr::Internal {
             message: "No string value defined for identifier token".to_string(),
+            span: self.span.clone(),
         };
 
         if self.token_type == TokenType::This {
@@ -47,7 +46,7 @@ impl fmt::Display for Token {
         write!(
             f,
             "Token: type={}, line={}, column={}",
-            self.token_type, self.line, self.column
+            self.token_type, self.span.line, self.span.column
         )?;
 
         if let Some(literal) = &self.literal {
@@ -25,11 +25,7 @@ impl Display for Literal {
         let str_val = match self {
             Self::String(str) => str,
             // TODO and also make it impossiblel to ovewrite native fns
-            Self::Func(func) => match func {
-                Func::Lox(_) => return write!(f, "[<function>{}]", func.name()),
-                Func::Native(_) => return write!(f, "[<native fn>{}]", func.name()),
-                Func::Class(class) => return write!(f, "{}", class),
-            },
+            Self::Func(func) => return write!(f, "{}", func),
             Self::Number(num) => return write!(f, "{}", num),
             Self::Nil => "Nil",
             Self::Boolean(bool) => {
@@ -12,7 +12,7 @@ use crate::lox::entities::func::Func;
 use crate::lox::entities::stmt::StmtClass;
 use crate::lox::entities::Class;
 
-use super::super::entities::eval::Interpreter;
+use super::super::entities::eval::{Interpreter, Output, TraceFrame};
 use super::super::entities::func::{Function, NativeFunction};
 use super::super::entities::stmt::{StmtFun, StmtReturn};
 use super::super::entities::{
@@ -23,15 +23,22 @@ use super::super::entities::{
 use super::visitor::{ExprVisitor, StmtVisitor};
 
 use loxrs_env::Scope;
-use loxrs_types::{LoxErr, Result};
+use loxrs_types::{LoxErr, Result, Span};
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_output(Output::stdout())
+    }
+
+    pub fn with_output(out: Output) -> Self {
         let scope = Self::setup_native_fns();
         Self {
             scope: Rc::clone(&scope),
             globals: Rc::clone(&scope),
             locals: RefCell::new(HashMap::new()),
+            out,
+            frames: vec![],
+            trace: vec![],
         }
     }
 
@@ -66,8 +73,8 @@ impl Interpreter {
                     }
 
                     Err(LoxErr::Eval {
-                        expr: assertion.to_string(),
-                        message: "assertion failed".to_owned(),
+                        message: format!("assertion failed: {assertion}"),
+                        span: Span::default(),
                     })
                 },
                 Rc::clone(&scope),
@@ -79,13 +86,40 @@ impl Interpreter {
     }
 
     pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<()> {
+        self.trace.clear();
         for s in stmts {
             trace!("evaluating statement:\n{:#?}", s);
-            self.exec_stmt(s)?;
+            self.exec_stmt(s)
+                .inspect_err(|e| self.record_trace(e.span()))?;
         }
         Ok(())
     }
 
+    /// Keeps the frames an error is about to unwind, unless an inner frame
+    /// already did so for the same error
+    pub fn record_trace(&mut self, at: &Span) {
+        if !self.trace.is_empty() {
+            return;
+        }
+        let mut span = at.clone();
+        for frame in self.frames.iter().rev() {
+            self.trace.push(TraceFrame {
+                function: Some(frame.function.clone()),
+                span,
+            });
+            span = frame.call_site.clone();
+        }
+        self.trace.push(TraceFrame {
+            function: None,
+            span,
+        });
+    }
+
+    /// Hands over the stack trace of the last runtime error
+    pub fn take_trace(&mut self) -> Vec<TraceFrame> {
+        std::mem::take(&mut self.trace)
+    }
+
     pub fn scope(&self) -> Rc<Scope<Value>> {
         Rc::clone(&self.scope)
     }
@@ -99,9 +133,12 @@ impl Interpreter {
 
         if let Some(distance) = self.locals.borrow().get(expr) {
             trace!("[lookup_var] found {} with value of {}", expr, distance);
-            return self.scope.get_at(*distance, name);
+            return self
+                .scope
+                .get_at(*distance, name)
+                .map_err(|e| e.or_at(&expr.span));
         }
-        self.globals.get(name)
+        self.globals.get(name).map_err(|e| e.or_at(&expr.span))
     }
 
     fn truthy(&self, val: &Value) -> Value {
@@ -112,32 +149,33 @@ impl Interpreter {
         })
     }
 
-    fn error(&self, expr: Vec<&Expr>, message: Option<&str>) -> LoxErr {
+    /// Closes `def` over the current scope. `name` is `None` for a function
+    /// expression
+    fn closure(&self, def: &ExprFunction, name: Option<&Token>) -> Result<Function> {
+        Ok(Function {
+            name: name
+                .map(|name| name.extract_identifier_str().map(str::to_owned))
+                .transpose()?,
+            def: def.clone(),
+            scope: self.scope(),
+            params: def.params.clone(),
+            is_initializer: false,
+        })
+    }
+
+    fn error(&self, span: Span, message: Option<&str>) -> LoxErr {
         LoxErr::Eval {
-            expr: expr
-                .iter()
-                .map(|el| el.to_string())
-                .collect::<Vec<String>>()
-                .join(", "),
             message: message
                 .unwrap_or("Expression evaluation failed")
                 .to_string(),
+            span,
         }
     }
 }
 
 impl ExprVisitor<Value> for Interpreter {
     fn func(&mut self, def: &ExprFunction) -> Result<Value> {
-        let scope = self.scope();
-
-        let func = Value::Func(Func::Lox(Function {
-            def: def.clone(),
-            scope,
-            params: def.params.clone(),
-            is_initializer: false,
-        }));
-
-        Ok(func)
+        Ok(Value::Func(Func::Lox(self.closure(def, None)?)))
     }
 
     fn literal(&mut self, literal: &Literal) -> Result<Value> {
@@ -146,7 +184,8 @@ impl ExprVisitor<Value> for Interpreter {
 
     fn unary(&mut self, right: &Expr, operator: &Token) -> Result<Value> {
         let eval_right: Value = self.eval(right)?;
-        let err_report = |other: Option<&str>| Err(self.error(vec![right], other));
+        let err_report =
+            |other: Option<&str>| Err(self.error(operator.span.to(&right.span), other));
 
         match operator.token_type {
             TokenType::Minus => match eval_right {
@@ -170,9 +209,9 @@ impl ExprVisitor<Value> for Interpreter {
 
         let err_report = |reason: Option<&str>| {
             Err(self.error(
-                vec![left, right],
+                left.span.to(&right.span),
                 reason.or(Some(&format!(
-                    "Unexpected values in binary expr: {:#?}",
+                    "Unexpected values in binary expr: {:?}",
                     (&left_val, &right_val)
                 ))),
             ))
@@ -188,9 +227,9 @@ impl ExprVisitor<Value> for Interpreter {
                 (Value::String(l), Value::Number(r)) => {
                     Ok(Value::String(l.to_string() + &r.to_string()))
                 }
-                (Value::Number(l), Value::String(r)) => Ok(Value::String(l.to_string() + &r)),
+                (Value::Number(l), Value::String(r)) => Ok(Value::String(l.to_string() + r)),
                 _ => err_report(Some(&format!(
-                    "unexpected types for addition operation: {:#?}",
+                    "unexpected types for addition operation: {:?}",
                     (&left_val, &right_val)
                 ))),
             },
@@ -251,6 +290,7 @@ impl ExprVisitor<Value> for Interpreter {
                 "{} not expected in `var` code path, programmer error",
                 expression
             ),
+            span: expression.span.clone(),
         })
     }
 
@@ -262,13 +302,15 @@ impl ExprVisitor<Value> for Interpreter {
             if let Ok(Value::Func(Func::Native(_func))) = self.globals.get(var_name) {
                 // TODO missing tests here
                 return Err(LoxErr::Eval {
-                    expr: var_name.to_owned(),
                     message: "Not allowed to override native function".to_owned(),
+                    span: expr.span.clone(),
                 });
             }
 
             if let Some(distance) = self.locals.borrow().get(expr) {
-                self.scope.assign_at(*distance, var_name, val.clone())?;
+                self.scope
+                    .assign_at(*distance, var_name, val.clone())
+                    .map_err(|e| e.or_at(&expr.span))?;
             } else {
                 self.globals.define(var_name, val.clone());
             }
@@ -281,6 +323,7 @@ impl ExprVisitor<Value> for Interpreter {
                 "{} not expected in `assign` code path, programmer error",
                 expr
             ),
+            span: expr.span.clone(),
         })
     }
 
@@ -289,10 +332,11 @@ impl ExprVisitor<Value> for Interpreter {
             Literal::Instance(instance) => {
                 trace!("getting {} from {}", name, instance.borrow());
                 Instance::get(instance, name.extract_identifier_str()?)
+                    .map_err(|e| e.or_at(&name.span))
             }
             _ => Err(LoxErr::Eval {
-                expr: expr.to_string(),
                 message: "Invalid call on non-instance value".to_string(),
+                span: expr.span.to(&name.span),
             }),
         }
     }
@@ -322,8 +366,8 @@ impl ExprVisitor<Value> for Interpreter {
                 Ok(val)
             }
             _ => Err(LoxErr::Eval {
-                expr: target.to_string(),
                 message: "Only instances can be accessed via fields (`.`)".to_string(),
+                span: target.span.to(&name.span),
             }),
         }
     }
@@ -346,9 +390,9 @@ impl ExprVisitor<Value> for Interpreter {
                 self.eval(right)
             }
             _ => Err(self.error(
-                vec![left, right],
+                left.span.to(&right.span),
                 Some(&format!(
-                    "Unexpected token type in logic expr: {:#?}",
+                    "Unexpected token type in logic expr: {:?}",
                     operator
                 )),
             )),
@@ -360,17 +404,21 @@ impl ExprVisitor<Value> for Interpreter {
             Literal::Func(val) => val,
             _ => {
                 return Err(LoxErr::Eval {
-                    expr: callee.to_string(),
                     message: "Invalid call on non-func value".to_string(),
+                    span: callee.span.clone(),
                 })
             }
         };
 
         if fun.arity() != args.len() {
             return Err(LoxErr::Eval {
-                expr: format!("{:?}", args),
-                message: format!("Expected {} args but got {}", fun.arity(), args.len())
-                    .to_string(),
+                message: format!(
+                    "Expected {} args but got {} when calling {}",
+                    fun.arity(),
+                    args.len(),
+                    fun
+                ),
+                span: callee.span.clone(),
             });
         }
 
@@ -380,7 +428,9 @@ impl ExprVisitor<Value> for Interpreter {
             args_eval.push(self.eval(arg)?);
         }
 
-        fun.call(self, args_eval)
+        // errors from natives and initializers don't know where the call was
+        fun.call(self, args_eval, &callee.span)
+            .map_err(|e| e.or_at(&callee.span))
     }
 
     fn this(&mut self, expression: &Expr) -> Result<Value> {
@@ -396,32 +446,37 @@ impl ExprVisitor<Value> for Interpreter {
                 "{} not expected in `this` expr code path, programmer error",
                 expression
             ),
+            span: expression.span.clone(),
         })
     }
 
     fn super_expr(&mut self, def: &Expr) -> Result<Value> {
         if let ExprKind::Super(sup) = &def.kind {
             if let Some(distance) = self.locals.borrow().get(def) {
-                if let Value::Func(Func::Class(superclass)) =
-                    self.scope.get_at(*distance, "super")?
-                {
+                let get_at = |distance, name| {
+                    self.scope
+                        .get_at(distance, name)
+                        .map_err(|e| e.or_at(&def.span))
+                };
+                if let Value::Func(Func::Class(superclass)) = get_at(*distance, "super")? {
                     let method_name = sup.method.extract_identifier_str()?;
                     if let Some(method) = superclass.find_method(method_name) {
-                        if let Value::Instance(this) = self.scope.get_at(*distance - 1, "this")? {
+                        if let Value::Instance(this) = get_at(*distance - 1, "this")? {
                             return Ok(Value::Func(Func::Lox(method.bind(this))));
                         }
                         return Err(LoxErr::Eval {
-                            expr: self.scope.to_string(),
                             message: "`this` not a Lox instance in scope".to_owned(),
+                            span: def.span.clone(),
                         });
                     }
                     return Err(LoxErr::Eval {
-                        expr: superclass.to_string(),
                         message: format!("method name {method_name} not found in superclass"),
+                        span: sup.method.span.clone(),
                     });
                 }
                 return Err(LoxErr::Internal {
                     message: format!("`super` value not a class in scope: {},", self.scope),
+                    span: def.span.clone(),
                 });
 
                 // return Literal
@@ -433,6 +488,7 @@ impl ExprVisitor<Value> for Interpreter {
                 "{} not expected in `super` code path, programmer error",
                 def
             ),
+            span: def.span.clone(),
         })
     }
 }
@@ -457,7 +513,10 @@ impl StmtVisitor for Interpreter {
     fn print_stmt(&mut self, stmt: &StmtPrint) -> Result<Option<Value>> {
         let val = self.eval(&stmt.expr)?;
         debug!("the returned value is: {val}");
-        println!("==> {}", val);
+        self.out.writeln(&val).map_err(|e| LoxErr::Internal {
+            message: format!("couldn't print: {e}"),
+            span: stmt.expr.span.clone(),
+        })?;
         Ok(None)
     }
 
@@ -484,10 +543,12 @@ impl StmtVisitor for Interpreter {
     fn fun_stmt(&mut self, stmt: &StmtFun) -> Result<Option<Value>> {
         trace!("assigning the following env to {:?}: {}", stmt, &self.scope);
 
-        let func = self.func(&stmt.def)?;
+        let func = self.closure(&stmt.def, Some(&stmt.name))?;
 
-        self.scope
-            .define(stmt.name.extract_identifier_str()?, func.clone());
+        self.scope.define(
+            stmt.name.extract_identifier_str()?,
+            Value::Func(Func::Lox(func)),
+        );
 
         Ok(None)
     }
@@ -533,7 +594,10 @@ impl StmtVisitor for Interpreter {
         let mut res = self.eval(&stmt.expr)?;
 
         while let Literal::Boolean(true) = self.truthy(&res) {
-            self.exec_stmt(&stmt.stmt)?;
+            // a `return` in the body leaves the loop along with the function
+            if let Some(val) = self.exec_stmt(&stmt.stmt)? {
+                return Ok(Some(val));
+            }
             res = self.eval(&stmt.expr)?;
         }
         Ok(None)
@@ -553,8 +617,8 @@ impl StmtVisitor for Interpreter {
                 }
                 _ => {
                     return Err(LoxErr::Eval {
-                        expr: expr.to_string(),
                         message: "Superclass must be a class".to_owned(),
+                        span: expr.span.clone(),
                     })
                 }
             }
@@ -571,25 +635,15 @@ impl StmtVisitor for Interpreter {
         let mut methods: HashMap<String, Function> = HashMap::new();
 
         for method in stmt.methods.iter() {
-            match self.func(&method.def)? {
-                Value::Func(Func::Lox(func)) => {
-                    let method_name = method.name.extract_identifier_str()?;
-                    let is_initializer = method_name == "init";
-                    methods.insert(
-                        method_name.to_owned(),
-                        Function {
-                            is_initializer,
-                            ..func
-                        },
-                    );
-                }
-                _ => {
-                    return Err(LoxErr::Eval {
-                        expr: method.def.to_string(),
-                        message: "Expected a method definition within the class".to_owned(),
-                    })
-                }
-            }
+            let func = self.closure(&method.def, Some(&method.name))?;
+            let method_name = method.name.extract_identifier_str()?;
+            methods.insert(
+                method_name.to_owned(),
+                Function {
+                    is_initializer: method_name == "init",
+                    ..func
+                },
+            );
         }
 
         if let Some(prev) = prev_scope {
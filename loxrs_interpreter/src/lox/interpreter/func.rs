@@ -2,15 +2,27 @@ use std::rc::Rc;
 
 use log::trace;
 use loxrs_env::Scope;
-use loxrs_types::Result;
+use loxrs_types::{Result, Span};
 
 use crate::lox::{
-    entities::{class::Instance, eval::Interpreter, func::Func, Value},
+    entities::{
+        class::Instance,
+        eval::{CallFrame, Interpreter},
+        func::Func,
+        Value,
+    },
     interpreter::visitor::StmtVisitor,
 };
 
 impl Func {
-    pub fn call(&mut self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value> {
+    /// `call_site` is where the caller called the function from, which the
+    /// stack trace of an error inside it points at
+    pub fn call(
+        &mut self,
+        interpreter: &mut Interpreter,
+        args: Vec<Value>,
+        call_site: &Span,
+    ) -> Result<Value> {
         match self {
             Func::Lox(e) => {
                 let scope = Scope::from_parent(Rc::clone(&e.scope));
@@ -28,12 +40,18 @@ impl Func {
                     e.name(),
                     scope,
                 );
+                interpreter.frames.push(CallFrame {
+                    function: e.name().to_owned(),
+                    call_site: call_site.clone(),
+                });
                 let res = interpreter
                     .block_stmt(&e.def.body, scope)
                     .map(|el| match el {
                         Some(val) => val,
                         None => Value::Nil,
-                    });
+                    })
+                    .inspect_err(|err| interpreter.record_trace(err.span()));
+                interpreter.frames.pop();
                 if e.is_initializer {
                     return e.scope.get_at(0, "this");
                 }
@@ -51,7 +69,7 @@ impl Func {
                 let instance = Instance::new(Rc::clone(class));
                 if let Some(init) = class.find_method("init") {
                     let mut init_func = Func::Lox(init.bind(Rc::clone(&instance)));
-                    init_func.call(interpreter, args)?;
+                    init_func.call(interpreter, args, call_site)?;
                 }
                 Ok(Value::Instance(instance))
             }
@@ -65,12 +83,4 @@ impl Func {
             Func::Class(class) => class.find_method("init").map(|f| f.arity()).unwrap_or(0),
         }
     }
-
-    pub fn name(&self) -> &str {
-        match self {
-            Func::Lox(e) => e.name(),
-            Func::Native(e) => e.name(),
-            Func::Class(e) => &e.name,
-        }
-    }
 }
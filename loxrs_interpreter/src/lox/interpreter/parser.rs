@@ -1,4 +1,4 @@
-use log::{error, trace};
+use log::trace;
 
 use crate::lox::entities::expr::{
     ExprAssign, ExprBinary, ExprCall, ExprFunction, ExprGet, ExprGrouping, ExprKind, ExprSet,
@@ -19,11 +19,17 @@ const MAX_ARGS_LEN: usize = 255;
 pub struct Parser {
     pub tokens: Vec<Token>,
     current: usize,
+    /// every syntax error found so far, parsing carries on after each one
+    errors: Vec<LoxErr>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            errors: vec![],
+        }
     }
 
     fn advance(&mut self) -> &Token {
@@ -59,9 +65,8 @@ impl Parser {
 
     fn error(&self, err_token: &Token, message: &str) -> LoxErr {
         LoxErr::Parse {
-            token: message.to_string(),
-            line: err_token.line.to_string(),
-            column: err_token.column.to_string(),
+            message: message.to_string(),
+            span: err_token.span.clone(),
         }
     }
 
@@ -100,53 +105,66 @@ impl Parser {
     }
 
     /// production rules
-    pub fn parse(&mut self) -> Result<Vec<Stmt>> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<LoxErr>> {
         let mut statements: Vec<Stmt> = vec![];
         while !self.is_at_end() {
-            if let Some(stmt) = self.stmt() {
+            if let Some(stmt) = self.declaration() {
                 statements.push(stmt);
             }
         }
-        Ok(statements)
+
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    fn stmt(&mut self) -> Option<Stmt> {
+    /// A declaration or a statement. When it's malformed the error is recorded
+    /// and parsing picks up again at the next statement, so a single run reports
+    /// every syntax error in the program
+    fn declaration(&mut self) -> Option<Stmt> {
         let stmt;
-        // declarations
         if self.matches(&[TokenType::Class]).is_some() && self.check(&TokenType::Identifier) {
             stmt = self.class_stmt();
         } else if self.matches(&[TokenType::Fun]).is_some() && self.check(&TokenType::Identifier) {
             stmt = self.fun_stmt("function");
         } else if self.matches(&[TokenType::Var]).is_some() {
             stmt = self.var_stmt();
-        }
-        // statements
-        else if self.matches(&[TokenType::For]).is_some() {
-            stmt = self.for_stmt();
-        } else if self.matches(&[TokenType::If]).is_some() {
-            stmt = self.if_stmt();
-        } else if self.matches(&[TokenType::Print]).is_some() {
-            stmt = self.print_stmt();
-        } else if self.matches(&[TokenType::Return]).is_some() {
-            stmt = self.return_stmt();
-        } else if self.matches(&[TokenType::While]).is_some() {
-            stmt = self.while_stmt();
-        } else if self.matches(&[TokenType::LeftBrace]).is_some() {
-            stmt = self.block_stmt();
         } else {
-            stmt = self.expr_stmt();
+            stmt = self.stmt();
         }
 
         match stmt {
             Ok(val) => Some(val),
             Err(err) => {
-                error!("{}", err);
+                self.errors.push(err);
                 self.synchronize();
                 None
             }
         }
     }
 
+    /// Any statement other than a declaration, which is all the body of a
+    /// loop or an `if` may be
+    fn stmt(&mut self) -> Result<Stmt> {
+        if self.matches(&[TokenType::For]).is_some() {
+            self.for_stmt()
+        } else if self.matches(&[TokenType::If]).is_some() {
+            self.if_stmt()
+        } else if self.matches(&[TokenType::Print]).is_some() {
+            self.print_stmt()
+        } else if self.matches(&[TokenType::Return]).is_some() {
+            self.return_stmt()
+        } else if self.matches(&[TokenType::While]).is_some() {
+            self.while_stmt()
+        } else if self.matches(&[TokenType::LeftBrace]).is_some() {
+            self.block_stmt()
+        } else {
+            self.expr_stmt()
+        }
+    }
+
     fn class_stmt(&mut self) -> Result<Stmt> {
         let name = self
             .consume(
@@ -183,7 +201,10 @@ impl Parser {
         Ok(Stmt::Class(StmtClass {
             name,
             methods,
-            superclass: superclass.map(|e| Expr::new(ExprKind::Var(e))),
+            superclass: superclass.map(|e| {
+                let span = e.span.clone();
+                Expr::new(ExprKind::Var(e), span)
+            }),
         }))
     }
 
@@ -195,18 +216,16 @@ impl Parser {
             "Expected `)` after `while` condition.",
         )?;
 
-        if let Some(stmt) = self.stmt() {
-            Ok(Stmt::While(StmtWhile {
-                stmt: Box::new(stmt),
-                expr,
-            }))
-        } else {
-            Err(self.error(self.peek(), "Error in `while` statement body"))
-        }
+        let stmt = self.stmt()?;
+        Ok(Stmt::While(StmtWhile {
+            stmt: Box::new(stmt),
+            expr,
+        }))
     }
 
     /// Desugars a `for` into `StmtBlock` and `StmtWhile` statements.
     fn for_stmt(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().span.clone();
         self.consume(&TokenType::LeftParen, "Expected `(` after `for` statement.")?;
 
         // init block
@@ -233,34 +252,28 @@ impl Parser {
         }
         self.consume(&TokenType::RightParen, "Expected `)` after `for` clause.")?;
 
-        let mut body = self
-            .stmt()
-            .ok_or(self.error(self.peek(), "Error in `for` statement clause"))?;
+        let mut body = self.stmt()?;
 
-        if incr.is_some() {
+        if let Some(expr) = incr {
             body = Stmt::Block(StmtBlock {
-                stmts: vec![
-                    body,
-                    Stmt::Expr(StmtExpr {
-                        expr: incr.unwrap(),
-                    }),
-                ],
+                stmts: vec![body, Stmt::Expr(StmtExpr { expr })],
             });
         }
 
         if cond.is_none() {
-            cond = Some(Expr::new(ExprKind::Literal(Box::new(Literal::Boolean(
-                false,
-            )))));
+            cond = Some(Expr::new(
+                ExprKind::Literal(Box::new(Literal::Boolean(false))),
+                keyword,
+            ));
         }
         body = Stmt::While(StmtWhile {
             expr: cond.unwrap(),
             stmt: Box::new(body),
         });
 
-        if init.is_some() {
+        if let Some(init) = init {
             body = Stmt::Block(StmtBlock {
-                stmts: vec![init.unwrap(), body],
+                stmts: vec![init, body],
             });
         }
         Ok(body)
@@ -271,22 +284,16 @@ impl Parser {
         let cond = self.expression()?;
         self.consume(&TokenType::RightParen, "Expected `)` after `if` condition.")?;
 
-        let then = self.stmt();
-        if then.is_none() {
-            return Err(self.error(self.peek(), "Error in `if` statement clause"));
-        }
+        let then = self.stmt()?;
 
         let mut else_stmt = None;
         if self.matches(&[TokenType::Else]).is_some() {
-            else_stmt = self.stmt();
-            if else_stmt.is_none() {
-                return Err(self.error(self.peek(), "Error in `if` statement `else` clause"));
-            }
+            else_stmt = Some(self.stmt()?);
         }
 
         Ok(Stmt::If(StmtIf {
             cond,
-            then: Box::new(then.unwrap()),
+            then: Box::new(then),
             else_stmt: else_stmt.map(Box::new),
         }))
     }
@@ -299,7 +306,10 @@ impl Parser {
 
     fn return_stmt(&mut self) -> Result<Stmt> {
         let keyword = self.previous().clone();
-        let mut val = Expr::new(ExprKind::Literal(Box::new(Value::Nil)));
+        let mut val = Expr::new(
+            ExprKind::Literal(Box::new(Value::Nil)),
+            keyword.span.clone(),
+        );
 
         if !self.check(&TokenType::SemiColon) {
             val = self.expression()?;
@@ -316,7 +326,7 @@ impl Parser {
         let mut stmts: Vec<Stmt> = Vec::new();
 
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            if let Some(s) = self.stmt() {
+            if let Some(s) = self.declaration() {
                 stmts.push(s)
             }
         }
@@ -348,14 +358,10 @@ impl Parser {
                     // we throw the error here nonetheless
                     let token = self.peek();
 
-                    return Err(LoxErr::Parse {
-                        token: format!(
-                            "No more than {} params are allowed for {}",
-                            MAX_ARGS_LEN, token
-                        ),
-                        line: token.line.to_string(),
-                        column: token.column.to_string(),
-                    });
+                    return Err(self.error(
+                        token,
+                        &format!("No more than {} params are allowed", MAX_ARGS_LEN),
+                    ));
                 }
                 params.push(
                     self.consume(&TokenType::Identifier, "expected parameter name")?
@@ -384,11 +390,10 @@ impl Parser {
                     body: block,
                 },
             })),
-            _ => Err(LoxErr::Parse {
-                token: format!("invalid statement in {} declaration", kind),
-                line: token.line.to_string(),
-                column: token.column.to_string(),
-            }),
+            _ => Err(self.error(
+                &token,
+                &format!("invalid statement in {} declaration", kind),
+            )),
         }
     }
 
@@ -418,24 +423,32 @@ impl Parser {
 
         if let Some(eq_token) = self.matches(&[TokenType::Equal]).cloned() {
             let val = self.assignment()?;
+            let span = or_expr.span.to(&val.span);
 
             match or_expr.kind {
                 ExprKind::Var(token) => {
-                    return Ok(Expr::new(ExprKind::Assign(Box::new(ExprAssign {
-                        name: token,
-                        expression: val,
-                    }))))
+                    return Ok(Expr::new(
+                        ExprKind::Assign(Box::new(ExprAssign {
+                            name: token,
+                            expression: val,
+                        })),
+                        span,
+                    ))
                 }
                 ExprKind::Get(get) => {
-                    return Ok(Expr::new(ExprKind::Set(Box::new(ExprSet {
-                        name: get.name,
-                        target: get.expr,
-                        value: val,
-                    }))))
+                    return Ok(Expr::new(
+                        ExprKind::Set(Box::new(ExprSet {
+                            name: get.name,
+                            target: get.expr,
+                            value: val,
+                        })),
+                        span,
+                    ))
                 }
                 _ => {
-                    // explicitly not returning the error, but displaying it
-                    error!("{}", self.error(&eq_token, "Invalid assignment target"));
+                    // the parser isn't confused, so there's no need to synchronize
+                    let err = self.error(&eq_token, "Invalid assignment target");
+                    self.errors.push(err);
                 }
             }
         }
@@ -449,11 +462,15 @@ impl Parser {
         while self.matches(&[TokenType::Or]).is_some() {
             let operator = self.previous().clone();
             let right = self.and()?;
-            expr = Expr::new(ExprKind::Logical(Box::new(ExprLogical {
-                left: expr,
-                right,
-                operator,
-            })));
+            let span = expr.span.to(&right.span);
+            expr = Expr::new(
+                ExprKind::Logical(Box::new(ExprLogical {
+                    left: expr,
+                    right,
+                    operator,
+                })),
+                span,
+            );
         }
         Ok(expr)
     }
@@ -464,11 +481,15 @@ impl Parser {
         while self.matches(&[TokenType::And]).is_some() {
             let operator = self.previous().clone();
             let right = self.equality()?;
-            expr = Expr::new(ExprKind::Logical(Box::new(ExprLogical {
-                left: expr,
-                right,
-                operator,
-            })));
+            let span = expr.span.to(&right.span);
+            expr = Expr::new(
+                ExprKind::Logical(Box::new(ExprLogical {
+                    left: expr,
+                    right,
+                    operator,
+                })),
+                span,
+            );
         }
         Ok(expr)
     }
@@ -481,11 +502,15 @@ impl Parser {
         {
             let operator = self.previous().clone();
             let right = self.comparison()?;
-            expr = Expr::new(ExprKind::Binary(Box::new(ExprBinary {
-                left: expr,
-                right,
-                operator,
-            })));
+            let span = expr.span.to(&right.span);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(ExprBinary {
+                    left: expr,
+                    right,
+                    operator,
+                })),
+                span,
+            );
         }
         Ok(expr)
     }
@@ -503,11 +528,15 @@ impl Parser {
         {
             let operator = self.previous().clone();
             let right = self.term()?;
-            expr = Expr::new(ExprKind::Binary(Box::new(ExprBinary {
-                left: expr,
-                right,
-                operator,
-            })));
+            let span = expr.span.to(&right.span);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(ExprBinary {
+                    left: expr,
+                    right,
+                    operator,
+                })),
+                span,
+            );
         }
         Ok(expr)
     }
@@ -517,11 +546,15 @@ impl Parser {
         while self.matches(&[TokenType::Minus, TokenType::Plus]).is_some() {
             let operator = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::new(ExprKind::Binary(Box::new(ExprBinary {
-                left: expr,
-                right,
-                operator,
-            })));
+            let span = expr.span.to(&right.span);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(ExprBinary {
+                    left: expr,
+                    right,
+                    operator,
+                })),
+                span,
+            );
         }
         Ok(expr)
     }
@@ -531,11 +564,15 @@ impl Parser {
         while self.matches(&[TokenType::Slash, TokenType::Star]).is_some() {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::new(ExprKind::Binary(Box::new(ExprBinary {
-                left: expr,
-                right,
-                operator,
-            })));
+            let span = expr.span.to(&right.span);
+            expr = Expr::new(
+                ExprKind::Binary(Box::new(ExprBinary {
+                    left: expr,
+                    right,
+                    operator,
+                })),
+                span,
+            );
         }
         Ok(expr)
     }
@@ -544,10 +581,11 @@ impl Parser {
         if self.matches(&[TokenType::Bang, TokenType::Minus]).is_some() {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            return Ok(Expr::new(ExprKind::Unary(Box::new(ExprUnary {
-                right,
-                operator,
-            }))));
+            let span = operator.span.to(&right.span);
+            return Ok(Expr::new(
+                ExprKind::Unary(Box::new(ExprUnary { right, operator })),
+                span,
+            ));
         }
         self.call()
     }
@@ -563,10 +601,9 @@ impl Parser {
                     &TokenType::Identifier,
                     "expected identifier name after `.`.",
                 )?;
-                expr = Expr::new(ExprKind::Get(Box::new(ExprGet {
-                    name: name.clone(),
-                    expr,
-                })))
+                let name = name.clone();
+                let span = expr.span.to(&name.span);
+                expr = Expr::new(ExprKind::Get(Box::new(ExprGet { name, expr })), span)
             } else {
                 break;
             }
@@ -582,14 +619,10 @@ impl Parser {
                     // we throw the error here nonetheless
                     let token = self.peek();
 
-                    return Err(LoxErr::Parse {
-                        token: format!(
-                            "No more than {} args are allowed for {}",
-                            MAX_ARGS_LEN, token
-                        ),
-                        line: token.line.to_string(),
-                        column: token.column.to_string(),
-                    });
+                    return Err(self.error(
+                        token,
+                        &format!("No more than {} args are allowed", MAX_ARGS_LEN),
+                    ));
                 }
                 args.push(self.expression()?);
                 trace!("call args so far: {:?}", args);
@@ -599,29 +632,38 @@ impl Parser {
             }
         }
 
-        let paren = self.consume(&TokenType::RightParen, "Expected ) after args list")?;
-        Ok(Expr::new(ExprKind::Call(Box::new(ExprCall {
-            callee,
-            paren: paren.clone(),
-            args,
-        }))))
+        let paren = self
+            .consume(&TokenType::RightParen, "Expected ) after args list")?
+            .clone();
+        let span = callee.span.to(&paren.span);
+        Ok(Expr::new(
+            ExprKind::Call(Box::new(ExprCall {
+                callee,
+                paren,
+                args,
+            })),
+            span,
+        ))
     }
 
     fn primary(&mut self) -> Result<Expr> {
-        if self.matches(&[TokenType::False]).is_some() {
-            return Ok(Expr::new(ExprKind::Literal(Box::new(Literal::Boolean(
-                false,
-            )))));
+        let literal = |token: &Token, literal: Literal| {
+            Ok(Expr::new(
+                ExprKind::Literal(Box::new(literal)),
+                token.span.clone(),
+            ))
+        };
+
+        if let Some(token) = self.matches(&[TokenType::False]) {
+            return literal(token, Literal::Boolean(false));
         }
 
-        if self.matches(&[TokenType::True]).is_some() {
-            return Ok(Expr::new(ExprKind::Literal(Box::new(Literal::Boolean(
-                true,
-            )))));
+        if let Some(token) = self.matches(&[TokenType::True]) {
+            return literal(token, Literal::Boolean(true));
         }
 
-        if self.matches(&[TokenType::Nil]).is_some() {
-            return Ok(Expr::new(ExprKind::Literal(Box::new(Literal::Nil))));
+        if let Some(token) = self.matches(&[TokenType::Nil]) {
+            return literal(token, Literal::Nil);
         }
 
         if let Some(token) = self.matches(&[TokenType::Super]) {
@@ -629,45 +671,48 @@ impl Parser {
 
             self.consume(&TokenType::Dot, "Expected `.` after `super` token")?;
 
-            return Ok(Expr::new(ExprKind::Super(Box::new(ExprSuper {
-                keyword,
-                method: self
-                    .consume(
-                        &TokenType::Identifier,
-                        "Expected an identifier after `super.`",
-                    )?
-                    .clone(),
-            }))));
+            let method = self
+                .consume(
+                    &TokenType::Identifier,
+                    "Expected an identifier after `super.`",
+                )?
+                .clone();
+            let span = keyword.span.to(&method.span);
+            return Ok(Expr::new(
+                ExprKind::Super(Box::new(ExprSuper { keyword, method })),
+                span,
+            ));
         }
 
         if let Some(token) = self.matches(&[TokenType::This]) {
-            return Ok(Expr::new(ExprKind::This(token.clone())));
+            return Ok(Expr::new(ExprKind::This(token.clone()), token.span.clone()));
         }
 
         // handle string and num literals
         if let Some(token) = self.matches(&[TokenType::String, TokenType::Number]) {
-            return Ok(Expr::new(ExprKind::Literal(Box::new(
-                match &token.literal {
-                    Some(lit) => lit.clone(),
-                    None => Literal::Nil,
-                },
-            ))));
+            let value = token.literal.clone().unwrap_or(Literal::Nil);
+            return literal(token, value);
         }
 
         if let Some(token) = self.matches(&[TokenType::Identifier]) {
-            return Ok(Expr::new(ExprKind::Var(token.clone())));
+            return Ok(Expr::new(ExprKind::Var(token.clone()), token.span.clone()));
         }
 
-        if self.matches(&[TokenType::LeftParen]).is_some() {
+        if let Some(token) = self.matches(&[TokenType::LeftParen]) {
+            let open = token.span.clone();
             let inner_expr = self.expression()?;
-            self.consume(&TokenType::RightParen, "Expected ) after current token")?;
-            return Ok(Expr::new(ExprKind::Grouping(Box::new(ExprGrouping {
-                expression: inner_expr,
-            }))));
+            let close = self.consume(&TokenType::RightParen, "Expected ) after current token")?;
+            let span = open.to(&close.span);
+            return Ok(Expr::new(
+                ExprKind::Grouping(Box::new(ExprGrouping {
+                    expression: inner_expr,
+                })),
+                span,
+            ));
         }
 
         if let Some(token) = self.matches(&[TokenType::Identifier]) {
-            return Ok(Expr::new(ExprKind::Var(token.clone())));
+            return Ok(Expr::new(ExprKind::Var(token.clone()), token.span.clone()));
         }
 
         if self.matches(&[TokenType::Fun]).is_some() {
@@ -678,6 +723,7 @@ impl Parser {
     }
 
     fn func(&mut self) -> Result<Expr> {
+        let keyword = self.previous().span.clone();
         self.consume(&TokenType::LeftParen, "Expected `(` after `fun` keyword.")?;
 
         let mut params = vec![];
@@ -687,14 +733,10 @@ impl Parser {
                     // we throw the error here nonetheless
                     let token = self.peek();
 
-                    return Err(LoxErr::Parse {
-                        token: format!(
-                            "No more than {} params are allowed for {}",
-                            MAX_ARGS_LEN, token
-                        ),
-                        line: token.line.to_string(),
-                        column: token.column.to_string(),
-                    });
+                    return Err(self.error(
+                        token,
+                        &format!("No more than {} params are allowed", MAX_ARGS_LEN),
+                    ));
                 }
                 params.push(
                     self.consume(&TokenType::Identifier, "expected parameter name")?
@@ -714,15 +756,12 @@ impl Parser {
 
         match self.block_stmt() {
             Err(e) => Err(e),
-            Ok(Stmt::Block(body)) => Ok(Expr::new(ExprKind::Function(Box::new(ExprFunction {
-                params,
-                body,
-            })))),
-            _ => Err(LoxErr::Parse {
-                token: "invalid statement in function declaration".to_owned(),
-                line: token.line.to_string(),
-                column: token.column.to_string(),
-            }),
+            Ok(Stmt::Block(body)) => Ok(Expr::new(
+                ExprKind::Function(Box::new(ExprFunction { params, body })),
+                // up to the closing `}` of the body
+                keyword.to(&self.previous().span),
+            )),
+            _ => Err(self.error(&token, "invalid statement in function declaration")),
         }
     }
 }
@@ -1,26 +1,48 @@
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label},
+    files::SimpleFiles,
+    term::{
+        self,
+        termcolor::{ColorChoice, StandardStream},
+    },
+};
 use log::{error, trace};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::process::exit;
 use std::rc::Rc;
 use std::{fs, io};
 
-use crate::lox::entities::eval::Interpreter;
+use crate::lox::entities::eval::{Interpreter, TraceFrame};
 use crate::lox::interpreter::scan_parse;
+use loxrs_types::LoxErr;
 
 use super::resolver::Resolver;
 
+/// Every source the interpreter has seen, which the spans of errors point into
+type Files = SimpleFiles<String, String>;
+
 pub fn run_file(filename: &String) {
     println!("you provided a file: {filename}.");
 
     match fs::read_to_string(filename) {
         Ok(str) => {
+            let mut files = Files::new();
+            let file = files.add(filename.to_owned(), str.clone());
+
             let interpreter = Rc::new(RefCell::new(Interpreter::new()));
-            let _ = repl(interpreter, &str).inspect_err(|errs| {
-                for e in errs {
-                    error!("{:?}", e);
-                }
-            });
+            if let Err(errs) = repl(Rc::clone(&interpreter), file, &str) {
+                let trace = interpreter.borrow_mut().take_trace();
+                report_errors(&files, &errs, &trace);
+                let compile_error = errs.iter().any(|e| {
+                    matches!(
+                        e,
+                        LoxErr::Scan { .. } | LoxErr::Parse { .. } | LoxErr::Resolve { .. }
+                    )
+                });
+                exit(if compile_error { 65 } else { 70 }); // EX_DATAERR or EX_SOFTWARE
+            }
         }
         Err(e) => {
             error!("Error reading file: {e}");
@@ -34,6 +56,8 @@ pub fn run_prompt() {
     println!("Enter statements separated by ENTER.");
     println!("EXIT with CTRL-D.");
 
+    // functions declared on earlier lines keep pointing into them
+    let mut files = Files::new();
     let interpreter = Rc::new(RefCell::new(Interpreter::new()));
     loop {
         print!("> ");
@@ -49,19 +73,74 @@ pub fn run_prompt() {
                 continue;
             }
         };
-        let _ = repl(Rc::clone(&interpreter), &statement).inspect_err(|errs| {
-            for e in errs {
-                error!("{:?}", e);
-            }
-        });
+        let file = files.add("REPL input".to_owned(), statement.clone());
+        if let Err(errs) = repl(Rc::clone(&interpreter), file, &statement) {
+            let trace = interpreter.borrow_mut().take_trace();
+            report_errors(&files, &errs, &trace);
+        }
+    }
+}
+
+/// Errors are always shown, not just when logging is turned on, with the
+/// source they point at. A runtime error also lists the calls it unwound
+fn report_errors(files: &Files, errs: &[LoxErr], trace: &[TraceFrame]) {
+    let mut error_map: BTreeMap<&'static str, Diagnostic<usize>> = BTreeMap::new();
+    for err in errs {
+        let category = match err {
+            LoxErr::Scan { .. } => "Syntax Error",
+            LoxErr::Parse { .. } | LoxErr::Resolve { .. } => "Compile Error",
+            _ => "Runtime Error",
+        };
+        let diagnostic = error_map
+            .remove(category)
+            .unwrap_or_else(|| Diagnostic::error().with_message(category));
+
+        let span = err.span();
+        let diagnostic = if span.is_unknown() {
+            diagnostic.with_notes(vec![err.message().to_owned()])
+        } else {
+            diagnostic.with_labels(vec![
+                Label::primary(span.file, span.range.clone()).with_message(err.message())
+            ])
+        };
+        error_map.insert(category, diagnostic);
     }
+    if let Some(diagnostic) = error_map.remove("Runtime Error") {
+        let diagnostic = match trace.is_empty() {
+            true => diagnostic,
+            false => diagnostic.with_notes(vec![traceback(files, trace)]),
+        };
+        error_map.insert("Runtime Error", diagnostic);
+    }
+
+    let writer = StandardStream::stderr(ColorChoice::Always);
+    let config = term::Config::default();
+
+    for diagnostic in error_map.values() {
+        term::emit(&mut writer.lock(), &config, files, diagnostic).unwrap();
+    }
+}
+
+/// Lists where every frame was when the error happened, innermost first, as
+/// `[line N] in name()` like clox does
+fn traceback(files: &Files, trace: &[TraceFrame]) -> String {
+    let lines: Vec<String> = trace
+        .iter()
+        .map(|frame| match files.get(frame.span.file) {
+            Ok(file) => format!("{frame} at {}", file.name()),
+            Err(_) => frame.to_string(),
+        })
+        .collect();
+    format!("stack trace:\n{}", lines.join("\n"))
 }
 
+/// Runs `str`, which `file` identifies in the spans of any errors
 pub fn repl(
     interpreter: Rc<RefCell<Interpreter>>,
+    file: usize,
     str: &str,
-) -> Result<(), Vec<loxrs_types::LoxErr>> {
-    scan_parse(str).and_then(|stmts| {
+) -> Result<(), Vec<LoxErr>> {
+    scan_parse(file, str).and_then(|stmts| {
         Resolver::new(Rc::clone(&interpreter))
             .resolve(&stmts)
             .map_err(|e| vec![e])
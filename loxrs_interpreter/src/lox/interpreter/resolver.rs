@@ -18,7 +18,7 @@ use super::{
 };
 use log::trace;
 use loxrs_env::Scope;
-use loxrs_types::{LoxErr, Result};
+use loxrs_types::{LoxErr, Result, Span};
 
 #[derive(Default, Debug, Clone, PartialEq)]
 enum VarStatus {
@@ -42,10 +42,27 @@ impl Display for VarStatus {
     }
 }
 
+#[derive(Debug, Clone)]
+struct Local {
+    status: VarStatus,
+    /// where the variable was declared
+    span: Span,
+}
+
+impl Local {
+    /// `this` and `super`, which the class declaring them brings into scope
+    fn implicit(class: &Token) -> Self {
+        Self {
+            status: VarStatus::Assigned,
+            span: class.span.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Resolver {
     interpreter: Rc<RefCell<Interpreter>>,
-    stack: Vec<HashMap<String, VarStatus>>,
+    stack: Vec<HashMap<String, Local>>,
     curr_function: FuncType,
     curr_class: ClassType,
 }
@@ -56,7 +73,7 @@ impl Display for Resolver {
         for el in &self.stack {
             write!(f, "[")?;
             for (k, v) in el.iter() {
-                write!(f, "({}: {}) ", k, v)?;
+                write!(f, "({}: {}) ", k, v.status)?;
             }
             write!(f, "]")?;
         }
@@ -80,6 +97,7 @@ impl Resolver {
             return Err(LoxErr::Internal {
                 message: "Programmer error: cannot use `FuncType::None` within a function resolver"
                     .to_owned(),
+                span: stmt.name.span.clone(),
             });
         }
 
@@ -112,16 +130,17 @@ impl Resolver {
             let name_val = name.extract_identifier_str()?;
             if last.get(name_val).is_some() {
                 return Err(LoxErr::Resolve {
-                    message: format!(
-                        "Variable `{}` already declared in current scope\n in line: {}, col: {}",
-                        name_val, name.line, name.column
-                    ),
+                    message: format!("Variable `{}` already declared in current scope", name_val),
+                    span: name.span.clone(),
                 });
             }
 
             last.insert(
                 name.extract_identifier_str()?.to_owned(),
-                VarStatus::default(),
+                Local {
+                    status: VarStatus::default(),
+                    span: name.span.clone(),
+                },
             );
         }
 
@@ -131,17 +150,18 @@ impl Resolver {
     fn define(&mut self, name: &Token) -> Result<Option<Value>> {
         let var_name = name.extract_identifier_str()?;
         if let Some(last) = self.stack.last_mut() {
-            if !last.contains_key(var_name) {
-                return Err(LoxErr::Resolve {
-                            message: format!("Can't define local variable {} before it is declared\n at line: {}, col: {}", 
-                                var_name, name.line,
-                                name.column),
-                        });
+            match last.get_mut(var_name) {
+                Some(local) => local.status = VarStatus::Defined,
+                None => {
+                    return Err(LoxErr::Resolve {
+                        message: format!(
+                            "Can't define local variable {} before it is declared",
+                            var_name
+                        ),
+                        span: name.span.clone(),
+                    })
+                }
             }
-            last.insert(
-                name.extract_identifier_str()?.to_owned(),
-                VarStatus::Defined,
-            );
         }
 
         // TODO check if this should return an error here instead,
@@ -152,16 +172,20 @@ impl Resolver {
     fn assign(&mut self, name: &Token) -> Result<Option<Value>> {
         let var_name = name.extract_identifier_str()?;
         if let Some(last) = self.stack.last_mut() {
-            if !last
-                .get(var_name)
-                .is_some_and(|el| *el == VarStatus::Defined)
-            {
-                return Err(LoxErr::Resolve {
-                    message: format!("Can't assign local variable {} before it is defined\n at line: {}, col: {}", var_name, name.line,
-                        name.column),
-                });
+            match last.get_mut(var_name) {
+                Some(local) if local.status == VarStatus::Defined => {
+                    local.status = VarStatus::Assigned
+                }
+                _ => {
+                    return Err(LoxErr::Resolve {
+                        message: format!(
+                            "Can't assign local variable {} before it is defined",
+                            var_name
+                        ),
+                        span: name.span.clone(),
+                    })
+                }
             }
-            last.insert(var_name.to_owned(), VarStatus::Assigned);
         }
 
         Ok(None)
@@ -174,9 +198,10 @@ impl Resolver {
     fn end_scope(&mut self) -> Result<Option<Value>> {
         if let Some(stack) = self.stack.pop() {
             for (k, v) in stack {
-                if v != VarStatus::Assigned {
+                if v.status != VarStatus::Assigned {
                     return Err(LoxErr::Resolve {
                         message: format!("Variable `{}` not assigned", k),
+                        span: v.span,
                     });
                 }
             }
@@ -213,9 +238,9 @@ impl Resolver {
             if scope.contains_key(name) {
                 trace!("found! resolving {} within stack no.: {}", expr, idx,);
                 self.interpreter.as_ref().borrow_mut().resolve(expr, idx);
-                if assign {
+                if let Some(local) = scope.get_mut(name).filter(|_| assign) {
                     trace!("Also setting {} to assigned", name);
-                    scope.insert(name.to_owned(), VarStatus::Assigned);
+                    local.status = VarStatus::Assigned;
                 }
                 return Ok(None);
             }
@@ -257,10 +282,8 @@ impl StmtVisitor for Resolver {
     fn return_stmt(&mut self, stmt: &StmtReturn) -> Result<Option<Value>> {
         match self.curr_function {
             FuncType::None => Err(LoxErr::Resolve {
-                message: format!(
-                    "Can't return from non-function scope\n at line: {}, col: {}",
-                    stmt.keyword.line, stmt.keyword.column,
-                ),
+                message: "Can't return from non-function scope".to_owned(),
+                span: stmt.keyword.span.clone(),
             }),
             FuncType::Initializer => {
                 if let ExprKind::Literal(lit) = &stmt.val.kind {
@@ -270,10 +293,8 @@ impl StmtVisitor for Resolver {
                 }
 
                 Err(LoxErr::Resolve {
-                    message: format!(
-                        "Can't return a value from class initializer scope\n at line: {}, col: {}",
-                        stmt.keyword.line, stmt.keyword.column,
-                    ),
+                    message: "Can't return a value from class initializer scope".to_owned(),
+                    span: stmt.keyword.span.clone(),
                 })
             }
             FuncType::Function | FuncType::Method => self.resolve_expr(&stmt.val),
@@ -355,6 +376,7 @@ impl StmtVisitor for Resolver {
                     if var.extract_identifier_str()? == stmt.name.extract_identifier_str()? {
                         return Err(LoxErr::Resolve {
                             message: "classes cannot inherit from themselves".to_owned(),
+                            span: var.span.clone(),
                         });
                     }
                     self.curr_class = ClassType::SubClass;
@@ -365,7 +387,7 @@ impl StmtVisitor for Resolver {
                     self.begin_scope();
                     self.stack
                         .last_mut()
-                        .map(|scope| scope.insert("super".to_owned(), VarStatus::Assigned));
+                        .map(|scope| scope.insert("super".to_owned(), Local::implicit(&stmt.name)));
                 }
                 _ => {
                     return Err(LoxErr::Internal {
@@ -373,6 +395,7 @@ impl StmtVisitor for Resolver {
                             "{} not expected in `super` resolver code path, programmer error",
                             expr
                         ),
+                        span: expr.span.clone(),
                     })
                 }
             }
@@ -383,7 +406,7 @@ impl StmtVisitor for Resolver {
 
         self.stack
             .last_mut()
-            .map(|scope| scope.insert("this".to_owned(), VarStatus::Assigned));
+            .map(|scope| scope.insert("this".to_owned(), Local::implicit(&stmt.name)));
 
         for fun in stmt.methods.iter() {
             let func_type = if fun.name.extract_identifier_str()? == "init" {
@@ -452,14 +475,13 @@ impl ExprVisitor<Option<Value>> for Resolver {
         if let ExprKind::Var(var) = &expression.kind {
             trace!("var expr: {}", var);
             let name = var.extract_identifier_str()?;
-            if self
-                .stack
-                .last()
-                .is_some_and(|last| last.get(name).is_some_and(|el| *el == VarStatus::Declared))
-            {
+            if self.stack.last().is_some_and(|last| {
+                last.get(name)
+                    .is_some_and(|el| el.status == VarStatus::Declared)
+            }) {
                 return Err(LoxErr::Resolve {
-                    message: format!("Can't read local variable {} from its own initalizer\n at line: {}, col: {}", name, var.line, 
-                        var.column),
+                    message: format!("Can't read local variable {} from its own initalizer", name),
+                    span: var.span.clone(),
                 });
             }
 
@@ -473,6 +495,7 @@ impl ExprVisitor<Option<Value>> for Resolver {
                 "{} not expected in `var` code path, programmer error",
                 expression
             ),
+            span: expression.span.clone(),
         })
     }
 
@@ -497,6 +520,7 @@ impl ExprVisitor<Option<Value>> for Resolver {
                 "{} not expected in `assign` code path, programmer error",
                 expr
             ),
+            span: expr.span.clone(),
         })
     }
 
@@ -526,6 +550,7 @@ impl ExprVisitor<Option<Value>> for Resolver {
         if self.curr_class == ClassType::None {
             return Err(LoxErr::Resolve {
                 message: "Can't use the `this` keyword outside a class statement.".to_owned(),
+                span: expression.span.clone(),
             });
         }
 
@@ -539,6 +564,7 @@ impl ExprVisitor<Option<Value>> for Resolver {
                 "{} not expected in `this` code path, programmer error",
                 expression
             ),
+            span: expression.span.clone(),
         })
     }
 
@@ -546,9 +572,11 @@ impl ExprVisitor<Option<Value>> for Resolver {
         match self.curr_class {
             ClassType::None => Err(LoxErr::Resolve {
                 message: "Can't use the `super` keyword outside a class statement.".to_owned(),
+                span: def.span.clone(),
             }),
             ClassType::Class => Err(LoxErr::Resolve {
                 message: "Can't use the `super` keyword in a class without subclass.".to_owned(),
+                span: def.span.clone(),
             }),
             ClassType::SubClass => {
                 if let ExprKind::Super(_) = def.kind {
@@ -561,6 +589,7 @@ impl ExprVisitor<Option<Value>> for Resolver {
                         "{} not expected in `super` code path, programmer error",
                         def
                     ),
+                    span: def.span.clone(),
                 })
             }
         }
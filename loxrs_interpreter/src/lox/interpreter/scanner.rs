@@ -1,38 +1,67 @@
-use std::{f64, usize};
-
 use crate::lox::entities::{Literal, Stmt, Token, TokenType};
 use crate::lox::interpreter::parser;
 use log::{debug, trace};
 
-use loxrs_types::{LoxErr, Result};
+use loxrs_types::{LoxErr, Result, Span};
 
 #[derive(Debug)]
 pub struct Scanner {
+    file: usize,
     source: String,
     chars: Vec<char>,
+    /// the byte offset in `source` of every char, and one past the last
+    offsets: Vec<usize>,
     errors: Option<Vec<LoxErr>>,
     start: usize,
+    start_line: usize,
+    start_col: usize,
     current: usize,
-    line: i32,
+    line: usize,
     line_start: usize,
-    col: i32,
     tokens: Vec<Token>,
 }
 
 impl Scanner {
-    fn new(source: String) -> Self {
-        let chars = source.chars().collect();
-        Self {
+    fn new(file: usize, source: String) -> Self {
+        let (offsets, chars) = source.char_indices().unzip();
+        let mut scanner = Self {
+            file,
             source,
             chars,
+            offsets,
             start: 0,
+            start_line: 1,
+            start_col: 1,
             current: 0,
             line: 1,
             line_start: 0,
-            col: 1,
             errors: None,
             tokens: Vec::new(),
-        }
+        };
+        scanner.offsets.push(scanner.source.len());
+        scanner
+    }
+
+    fn begin_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_col = 1 + self.start - self.line_start;
+    }
+
+    /// The source between the start of the current token and `current`,
+    /// trimmed by `trim` chars on either side
+    fn lexeme(&self, trim: usize) -> &str {
+        &self.source[self.offsets[self.start + trim]..self.offsets[self.current - trim]]
+    }
+
+    /// Where the current token sits, starting from the line and column it began on
+    fn span(&self) -> Span {
+        Span::new(
+            self.file,
+            self.offsets[self.start]..self.offsets[self.current],
+            self.start_line,
+            self.start_col,
+        )
     }
 
     fn advance(&mut self) -> char {
@@ -67,23 +96,19 @@ impl Scanner {
     }
 
     fn is_at_end(&self) -> bool {
-        let curr = self.current;
-        let source_len = self.source.len();
-        curr >= source_len
+        self.current >= self.chars.len()
     }
 
     fn set_next_line(&mut self) {
         self.line += 1;
         self.line_start = self.current;
-        self.col = 1;
     }
 
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
+            if self.advance() == '\n' {
                 self.set_next_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
@@ -93,15 +118,11 @@ impl Scanner {
 
         // account for closing `"`
         self.advance();
-        let curr_col = self.curr_col();
 
         let str_token = Token::new(
             TokenType::String,
-            Some(Literal::String(String::from(
-                &self.source[(self.start + 1)..(self.current - 1)],
-            ))),
-            self.line,
-            curr_col,
+            Some(Literal::String(String::from(self.lexeme(1)))),
+            self.span(),
         );
 
         self.tokens.push(str_token);
@@ -122,15 +143,10 @@ impl Scanner {
             }
         }
 
-        let curr_col = self.curr_col();
-        match self.source[self.start..self.current].parse::<f64>() {
+        match self.lexeme(0).parse::<f64>() {
             Ok(num) => {
-                let num_token = Token::new(
-                    TokenType::Number,
-                    Some(Literal::Number(num)),
-                    self.line,
-                    curr_col,
-                );
+                let num_token =
+                    Token::new(TokenType::Number, Some(Literal::Number(num)), self.span());
                 self.tokens.push(num_token);
             }
             Err(err) => self.error(&format!("{}", err)),
@@ -142,9 +158,9 @@ impl Scanner {
             self.advance();
         }
 
-        let curr_str = &self.source[self.start..self.current];
+        let curr_str = self.lexeme(0);
 
-        let make_token = |t: TokenType| Token::new(t, None, self.line, self.curr_col());
+        let make_token = |t: TokenType| Token::new(t, None, self.span());
 
         let new_token = match curr_str {
             "and" => make_token(TokenType::And),
@@ -166,15 +182,14 @@ impl Scanner {
             str => Token::new(
                 TokenType::Identifier,
                 Some(Literal::String(str.to_string())),
-                self.line,
-                self.curr_col(),
+                self.span(),
             ),
         };
         self.tokens.push(new_token);
     }
 
     fn scan_token(&mut self) {
-        self.start = self.current;
+        self.begin_token();
         let c = self.advance();
 
         match c {
@@ -278,31 +293,21 @@ impl Scanner {
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        let curr_col = self.curr_col();
-        self.tokens
-            .push(Token::new(token_type, None, self.line, curr_col));
-    }
-
-    fn curr_col(&self) -> i32 {
-        if 1 + self.start >= self.line_start {
-            return (1 + self.start - self.line_start).try_into().unwrap();
-        }
-        self.current.try_into().unwrap()
+        self.tokens.push(Token::new(token_type, None, self.span()));
     }
 
     fn error(&mut self, message: &str) {
-        let err = || LoxErr::Scan {
-            line: self.line,
-            col: self.col,
+        let err = LoxErr::Scan {
             message: message.to_string(),
+            span: self.span(),
         };
 
         match &mut self.errors {
             Some(errs) => {
-                errs.push(err());
+                errs.push(err);
             }
             None => {
-                self.errors = Some(vec![err()]);
+                self.errors = Some(vec![err]);
             }
         };
     }
@@ -317,6 +322,7 @@ impl Scan for Scanner {
         while !self.is_at_end() {
             self.scan_token();
         }
+        self.begin_token();
         self.add_token(TokenType::Eof);
 
         match &self.errors {
@@ -326,14 +332,15 @@ impl Scan for Scanner {
     }
 }
 
-pub fn scan_parse(raw_s: &str) -> Result<Vec<Stmt>, Vec<LoxErr>> {
+/// `file` identifies the source in the spans of its tokens, expressions and errors
+pub fn scan_parse(file: usize, raw_s: &str) -> Result<Vec<Stmt>, Vec<LoxErr>> {
     debug!("received: \n{raw_s}");
-    let mut scanner = Scanner::new(String::from(raw_s));
+    let mut scanner = Scanner::new(file, String::from(raw_s));
 
     let tokens = scanner.scan()?;
 
     trace!("here are tokens: \n{:#?}", tokens);
     let mut parser = parser::Parser::new(tokens);
 
-    parser.parse().map_err(|e| vec![e])
+    parser.parse()
 }
@@ -0,0 +1,550 @@
+//! Runs the scripts under `spec/` and compares what they do against the
+//! expectations annotated in their comments, as the reference test suite does:
+//!
+//! - `// expect: value` is a line the script prints
+//! - `// expect runtime error: message` is a runtime error on that line
+//! - `// Error at 'token': message` is a compile error on that line, and
+//!   `// [line N] Error...` one on line `N`. `[java line N]` and `[c line N]`
+//!   only apply to the tree-walker and the VM respectively
+//!
+//! The same expectations drive both implementations through [Target]. The
+//! tree-walker runs in-process with its output captured, while the VM is a
+//! separate binary, which the harness builds with cargo before running it so
+//! that it is never missing or stale. Set `LOXRS_VM` to run a different build.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::BTreeMap,
+    env, fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::Command,
+    rc::Rc,
+};
+
+use loxrs_types::LoxErr;
+
+use crate::lox::{
+    entities::eval::{Interpreter, Output},
+    interpreter::reader::repl,
+};
+
+/// `benchmark` takes far too long to be worth running on every test run, and
+/// `expressions` and `scanning` test the book's early chapters, which print
+/// syntax trees and tokens rather than run the script
+const SKIPPED_DIRS: [&str; 3] = ["benchmark", "expressions", "scanning"];
+
+/// Scripts that describe what they print in prose rather than with `// expect:`,
+/// so there's nothing to check them against
+const UNANNOTATED_SCRIPTS: [&str; 1] = ["class/cake.lox"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    /// scanning, parsing and resolving, which all happen before the script runs
+    Compile,
+    Runtime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExpectedError {
+    category: Category,
+    line: usize,
+}
+
+#[derive(Debug, Default)]
+struct Expectations {
+    output: Vec<String>,
+    /// every error the script should report, the first of which is checked
+    errors: Vec<ExpectedError>,
+}
+
+impl Expectations {
+    fn parse(source: &str, dialect: &str) -> Self {
+        let mut expectations = Self::default();
+        for (idx, line) in source.lines().enumerate() {
+            let line_number = idx + 1;
+            // found anywhere on the line, as some scripts comment it out twice
+            if line.contains("// expect runtime error:") {
+                expectations.errors.push(ExpectedError {
+                    category: Category::Runtime,
+                    line: line_number,
+                });
+                continue;
+            }
+            let Some((_, comment)) = line.split_once("//") else {
+                continue;
+            };
+            let comment = comment.trim();
+
+            if let Some(value) = comment.strip_prefix("expect: ") {
+                expectations.output.push(value.to_owned());
+            } else if comment.starts_with("Error") {
+                expectations.errors.push(ExpectedError {
+                    category: Category::Compile,
+                    line: line_number,
+                });
+            } else if let Some(line) = compile_error_line(comment, dialect) {
+                expectations.errors.push(ExpectedError {
+                    category: Category::Compile,
+                    line,
+                });
+            }
+        }
+        expectations
+    }
+}
+
+/// The `N` of a `[line N] Error` or `[dialect line N] Error` annotation
+fn compile_error_line(comment: &str, dialect: &str) -> Option<usize> {
+    let (tag, rest) = comment.strip_prefix('[')?.split_once(']')?;
+    if !rest.trim_start().starts_with("Error") {
+        return None;
+    }
+    let line = match tag.split_once(' ')? {
+        ("line", line) => line,
+        (other, line) if other == dialect => line.strip_prefix("line ")?,
+        _ => return None,
+    };
+    line.parse().ok()
+}
+
+/// What running a script did
+#[derive(Debug, Default)]
+struct Outcome {
+    output: Vec<String>,
+    /// the category of the first error, and its line if the target knows it
+    error: Option<(Category, Option<usize>)>,
+    /// set when the implementation itself fell over, rather than the script
+    crash: Option<String>,
+}
+
+impl Outcome {
+    /// Describes the first way the outcome differs from `expected`, if any
+    fn mismatch(&self, expected: &Expectations) -> Option<String> {
+        if let Some(crash) = &self.crash {
+            return Some(format!("crashed: {crash}"));
+        }
+        for (idx, expected_line) in expected.output.iter().enumerate() {
+            match self.output.get(idx) {
+                Some(line) if line == expected_line => {}
+                Some(line) => {
+                    return Some(format!(
+                        "output line {}: expected `{expected_line}`, got `{line}`",
+                        idx + 1
+                    ))
+                }
+                None => return Some(format!("missing output `{expected_line}`")),
+            }
+        }
+        if let Some(extra) = self.output.get(expected.output.len()) {
+            return Some(format!("unexpected output `{extra}`"));
+        }
+
+        match (expected.errors.first(), self.error) {
+            (None, None) => None,
+            (None, Some((category, _))) => Some(format!("unexpected {category:?} error")),
+            (Some(first), None) => Some(format!(
+                "expected {:?} error on line {}",
+                first.category, first.line
+            )),
+            (Some(first), Some((category, line))) => {
+                if category != first.category {
+                    return Some(format!(
+                        "expected {:?} error, got {category:?} error",
+                        first.category
+                    ));
+                }
+                let on_expected_line = line.is_some_and(|line| {
+                    expected
+                        .errors
+                        .iter()
+                        .any(|err| err.category == category && err.line == line)
+                });
+                (!on_expected_line).then(|| {
+                    format!(
+                        "expected {category:?} error on line {}, got it on {}",
+                        first.line,
+                        line.map_or("no line".to_owned(), |line| format!("line {line}"))
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// An implementation of Lox the expectations can be checked against
+trait Target {
+    fn name(&self) -> &'static str;
+
+    /// Which of the `[java line N]` or `[c line N]` annotations apply
+    fn dialect(&self) -> &'static str;
+
+    /// Directories the target passes in full, and must keep passing
+    fn enforced(&self) -> &'static [&'static str];
+
+    /// `None` if the target can't run the script, which skips it
+    fn run(&self, path: &Path, source: &str) -> Option<Outcome>;
+}
+
+struct TreeWalker;
+
+impl TreeWalker {
+    /// Recursing without end would overflow the test's own stack
+    const UNRUNNABLE: [&'static str; 1] = ["stack_overflow.lox"];
+
+    const ENFORCED: [&'static str; 12] = [
+        "block",
+        "call",
+        "closure",
+        "comments",
+        "field",
+        "if",
+        "inheritance",
+        "print",
+        "spec",
+        "string",
+        "super",
+        "while",
+    ];
+}
+
+impl Target for TreeWalker {
+    fn name(&self) -> &'static str {
+        "loxrs_interpreter"
+    }
+
+    fn dialect(&self) -> &'static str {
+        "java"
+    }
+
+    fn enforced(&self) -> &'static [&'static str] {
+        &Self::ENFORCED
+    }
+
+    fn run(&self, path: &Path, source: &str) -> Option<Outcome> {
+        if Self::UNRUNNABLE.iter().any(|name| path.ends_with(name)) {
+            return None;
+        }
+
+        let buffer = Rc::new(RefCell::new(vec![]));
+        let interpreter = Interpreter::with_output(Output::new(buffer.clone()));
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            repl(Rc::new(RefCell::new(interpreter)), 0, source)
+        }));
+
+        let output = String::from_utf8_lossy(&buffer.borrow())
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        let outcome = match res {
+            Ok(res) => Outcome {
+                output,
+                error: res.err().and_then(|errs| errs.first().map(classify)),
+                crash: None,
+            },
+            Err(panic) => Outcome {
+                output,
+                error: None,
+                crash: Some(panic_message(panic)),
+            },
+        };
+        Some(outcome)
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "panicked".to_owned(),
+    }
+}
+
+fn classify(err: &LoxErr) -> (Category, Option<usize>) {
+    let category = match err {
+        LoxErr::Scan { .. } | LoxErr::Parse { .. } | LoxErr::Resolve { .. } => Category::Compile,
+        LoxErr::Eval { .. } | LoxErr::Undefined { .. } | LoxErr::Internal { .. } => {
+            Category::Runtime
+        }
+    };
+    let span = err.span();
+    (category, (!span.is_unknown()).then_some(span.line))
+}
+
+struct Vm {
+    name: &'static str,
+    binary: PathBuf,
+}
+
+impl Vm {
+    const ENFORCED: [&'static str; 26] = [
+        "assignment",
+        "block",
+        "bool",
+        "call",
+        "class",
+        "closure",
+        "comments",
+        "constructor",
+        "field",
+        "for",
+        "function",
+        "if",
+        "inheritance",
+        "logical_operator",
+        "method",
+        "nil",
+        "operator",
+        "print",
+        "regression",
+        "return",
+        "spec",
+        "string",
+        "super",
+        "this",
+        "variable",
+        "while",
+    ];
+
+    fn new() -> Self {
+        let binary = env::var_os("LOXRS_VM")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Self::build(None));
+        assert!(
+            binary.is_file(),
+            "no VM binary at {}, check `LOXRS_VM`",
+            binary.display()
+        );
+        Self {
+            name: "loxrs_vm",
+            binary,
+        }
+    }
+
+    /// A build that collects garbage on every allocation, which turns a
+    /// value the collector can't reach from its roots into a wrong result
+    fn stress_gc() -> Self {
+        Self {
+            name: "loxrs_vm stress_gc",
+            binary: Self::build(Some("stress_gc")),
+        }
+    }
+
+    /// Builds the VM into a target directory of its own, as the one the tests
+    /// run from stays locked while they do
+    fn build(feature: Option<&str>) -> PathBuf {
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+        // one directory per feature set, so neither build invalidates the other
+        let target_dir = match feature {
+            Some(feature) => workspace.join(format!("target/conformance-{feature}")),
+            None => workspace.join("target/conformance"),
+        };
+        let mut cargo = Command::new(env!("CARGO"));
+        cargo
+            .args(["build", "--quiet", "-p", "loxrs_vm", "--target-dir"])
+            .arg(&target_dir)
+            .current_dir(workspace);
+        if let Some(feature) = feature {
+            cargo.args(["--features", feature]);
+        }
+        let status = cargo.status().expect("couldn't run cargo to build the VM");
+        assert!(status.success(), "building the VM failed");
+        target_dir.join("debug/loxrs_vm")
+    }
+}
+
+impl Target for Vm {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn dialect(&self) -> &'static str {
+        "c"
+    }
+
+    fn enforced(&self) -> &'static [&'static str] {
+        &Self::ENFORCED
+    }
+
+    fn run(&self, path: &Path, _source: &str) -> Option<Outcome> {
+        let res = Command::new(&self.binary)
+            .arg(path)
+            .output()
+            .unwrap_or_else(|e| panic!("couldn't run {}: {e}", self.binary.display()));
+        let stdout = String::from_utf8_lossy(&res.stdout);
+        let stderr = strip_colors(&String::from_utf8_lossy(&res.stderr));
+
+        let output = stdout
+            .lines()
+            // the VM announces the file it was given before running it
+            .skip_while(|line| line.starts_with("you provided a file"))
+            .map(str::to_owned)
+            .collect();
+        let error = vm_error(&stderr);
+        // the VM exits with 65 or 70 after reporting an error, while a panic
+        // exits with 101 and a signal with no code at all
+        let crash = match (res.status.code(), &error) {
+            (Some(0), None) | (Some(65 | 70), Some(_)) => None,
+            _ => Some(res.status.to_string()),
+        };
+        Some(Outcome {
+            output,
+            error,
+            crash,
+        })
+    }
+}
+
+/// Reads the category and line of the first diagnostic the VM reported,
+/// which starts with `error: <category>` and then points at `file:line:col`
+fn vm_error(stderr: &str) -> Option<(Category, Option<usize>)> {
+    let mut lines = stderr.lines();
+    let header = lines.find_map(|line| line.strip_prefix("error: "))?;
+    let category = match header {
+        "Runtime Error" => Category::Runtime,
+        _ => Category::Compile,
+    };
+    let line = lines
+        .find_map(|line| line.trim_start().strip_prefix("┌─ "))
+        .and_then(|location| location.rsplit(':').nth(1))
+        .and_then(|line| line.parse().ok());
+    Some((category, line))
+}
+
+fn strip_colors(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip through the `m` ending the escape sequence
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+/// Results per directory under `spec/`, the scripts right inside it counting as `spec`
+type Report = BTreeMap<String, Tally>;
+
+fn spec_folder() -> PathBuf {
+    // `file!()` is relative to the workspace root
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let conformance = Path::new(file!());
+    workspace.join(conformance.parent().unwrap()).join("spec")
+}
+
+fn scripts(folder: &Path) -> Vec<(String, PathBuf)> {
+    let mut scripts = vec![];
+    for entry in fs::read_dir(folder).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            let dir = path.file_name().unwrap().to_string_lossy().to_string();
+            if SKIPPED_DIRS.contains(&dir.as_str()) {
+                continue;
+            }
+            for entry in fs::read_dir(&path).unwrap() {
+                scripts.push((dir.clone(), entry.unwrap().path()));
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            scripts.push(("spec".to_owned(), path));
+        }
+    }
+    scripts.sort();
+    scripts
+}
+
+/// Runs every script against `target`, or returns `None` if the target can't run
+fn check(target: &dyn Target, scripts: &[(String, PathBuf)]) -> Option<Report> {
+    let mut report = Report::new();
+    let mut ran_any = false;
+    for (dir, path) in scripts {
+        let source = fs::read_to_string(path).unwrap();
+        let tally = report.entry(dir.clone()).or_default();
+        if UNANNOTATED_SCRIPTS.iter().any(|name| path.ends_with(name)) {
+            tally.skipped += 1;
+            continue;
+        }
+        let Some(outcome) = target.run(path, &source) else {
+            tally.skipped += 1;
+            continue;
+        };
+        ran_any = true;
+
+        let expectations = Expectations::parse(&source, target.dialect());
+        match outcome.mismatch(&expectations) {
+            None => tally.passed += 1,
+            Some(mismatch) => {
+                tally.failed += 1;
+                let script = path.strip_prefix(spec_folder()).unwrap_or(path);
+                println!("{} FAILED {}: {mismatch}", target.name(), script.display());
+            }
+        }
+    }
+    ran_any.then_some(report)
+}
+
+fn print_table(reports: &[(&str, Option<Report>)]) {
+    print!("\n{:<20}", "directory");
+    for (name, _) in reports {
+        print!("{name:>20}");
+    }
+    println!();
+
+    let dirs = reports
+        .iter()
+        .find_map(|(_, report)| report.as_ref())
+        .map(|report| report.keys().cloned().collect())
+        .unwrap_or_else(Vec::new);
+    for dir in dirs {
+        print!("{dir:<20}");
+        for (_, report) in reports {
+            let cell = match report.as_ref().and_then(|report| report.get(&dir)) {
+                Some(tally) if tally.skipped > 0 => format!(
+                    "{}/{} ({} skipped)",
+                    tally.passed,
+                    tally.passed + tally.failed,
+                    tally.skipped
+                ),
+                Some(tally) => format!("{}/{}", tally.passed, tally.passed + tally.failed),
+                None => "not run".to_owned(),
+            };
+            print!("{cell:>20}");
+        }
+        println!();
+    }
+}
+
+#[test]
+fn conformance() {
+    let scripts = scripts(&spec_folder());
+    let targets: [Box<dyn Target>; 3] = [
+        Box::new(TreeWalker),
+        Box::new(Vm::new()),
+        Box::new(Vm::stress_gc()),
+    ];
+    let reports: Vec<_> = targets
+        .iter()
+        .map(|target| (target.name(), check(target.as_ref(), &scripts)))
+        .collect();
+    print_table(&reports);
+
+    for (target, (name, report)) in targets.iter().zip(&reports) {
+        let report = report
+            .as_ref()
+            .unwrap_or_else(|| panic!("{name} didn't run any script"));
+        for dir in target.enforced() {
+            let tally = &report[*dir];
+            assert_eq!(tally.failed, 0, "`spec/{dir}` no longer passes on {name}");
+        }
+    }
+}
@@ -5,10 +5,38 @@ use std::{
     rc::Rc,
 };
 
-use crate::lox::{entities::eval::Interpreter, interpreter::reader::repl};
+use loxrs_types::LoxErr;
+
+use crate::lox::{
+    entities::eval::{Interpreter, Output},
+    interpreter::reader::repl,
+};
+
+/// Fixtures under `pass/` that never scanned or parsed, kept as they are and
+/// checked to be rejected before they run
+const BROKEN_PASS_FIXTURES: [&str; 2] = [
+    // the only statement is missing its `;`
+    "simple.lox",
+    // a stray `"yo` opens a string that swallows the rest of the script
+    "ycomb.lox",
+];
+
+/// Spec fixtures that hold a syntax error the reference suite expects to be
+/// reported, so they are rejected before they run
+const SYNTAX_ERROR_SPEC_FIXTURES: [&str; 9] = [
+    "assignment/grouping.lox",
+    "assignment/infix_operator.lox",
+    "assignment/prefix_operator.lox",
+    "if/class_in_else.lox",
+    "if/class_in_then.lox",
+    "if/fun_in_else.lox",
+    "if/fun_in_then.lox",
+    "if/var_in_else.lox",
+    "if/var_in_then.lox",
+];
 
 fn traverse<
-    F: FnOnce(std::result::Result<(), std::vec::Vec<loxrs_types::LoxErr>>) -> bool + Copy,
+    F: FnOnce(&Path, std::result::Result<(), std::vec::Vec<loxrs_types::LoxErr>>) -> bool + Copy,
 >(
     source: &PathBuf,
     inspect: F,
@@ -16,13 +44,40 @@ fn traverse<
     for file in fs::read_dir(source).unwrap() {
         println!("Testing file: {:?}", file);
         let interpreter = Rc::new(RefCell::new(Interpreter::new()));
-        let str = fs::read_to_string(file.unwrap().path()).unwrap();
-        let res = repl(interpreter, &str);
+        let path = file.unwrap().path();
+        let str = fs::read_to_string(&path).unwrap();
+        let res = repl(interpreter, 0, &str);
         println!("testing output: {:?}", &res);
-        assert!(inspect(res));
+        assert!(inspect(&path, res));
     }
 }
 
+fn is_compile_error(errs: &[LoxErr]) -> bool {
+    errs.iter()
+        .all(|err| matches!(err, LoxErr::Scan { .. } | LoxErr::Parse { .. }))
+}
+
+/// Spec fixtures run cleanly unless they are listed as syntax errors
+fn spec_outcome(path: &Path, res: Result<(), Vec<LoxErr>>) -> bool {
+    match SYNTAX_ERROR_SPEC_FIXTURES
+        .iter()
+        .any(|name| path.ends_with(name))
+    {
+        true => res.is_err_and(|errs| is_compile_error(&errs)),
+        false => res.is_ok(),
+    }
+}
+
+/// Runs `source` and returns what it printed
+fn run_captured(source: &str) -> String {
+    let buffer = Rc::new(RefCell::new(vec![]));
+    let interpreter = Interpreter::with_output(Output::new(buffer.clone()));
+    let res = repl(Rc::new(RefCell::new(interpreter)), 0, source);
+    assert!(res.is_ok(), "{res:?}");
+    let output = String::from_utf8_lossy(&buffer.borrow()).into_owned();
+    output
+}
+
 fn get_test_folder() -> std::path::PathBuf {
     let mut cwd = env::current_dir().unwrap();
     let prefix = Path::new(file!().strip_suffix("runner.rs").unwrap());
@@ -33,59 +88,99 @@ fn get_test_folder() -> std::path::PathBuf {
 #[test]
 fn e2e_pass() {
     let folder = get_test_folder();
-    traverse(&folder.join("pass/"), |res| res.is_ok());
+    traverse(
+        &folder.join("pass/"),
+        |path, res| match BROKEN_PASS_FIXTURES.iter().any(|name| path.ends_with(name)) {
+            true => res.is_err_and(|errs| is_compile_error(&errs)),
+            false => res.is_ok(),
+        },
+    );
 }
 
 #[test]
 fn e2e_fail() {
     let folder = get_test_folder();
-    traverse(&folder.join("fail/"), |res| res.is_err());
+    traverse(&folder.join("fail/"), |_, res| res.is_err());
 }
 
 #[test]
 fn spec_samples() {
     let folder = get_test_folder();
-    traverse(&folder.join("samples/"), |res| res.is_ok());
+    traverse(&folder.join("samples/"), |_, res| res.is_ok());
 }
 
 #[test]
 fn spec_assignment() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/assignment"), |res| res.is_ok());
+    traverse(&folder.join("spec/assignment"), spec_outcome);
 }
 
 #[test]
 fn spec_block() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/block"), |res| res.is_ok());
+    traverse(&folder.join("spec/block"), |_, res| res.is_ok());
 }
 
 #[test]
 fn spec_bool() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/bool"), |res| res.is_ok());
+    traverse(&folder.join("spec/bool"), |_, res| res.is_ok());
 }
 
 #[test]
 fn spec_call() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/call"), |res| res.is_err());
+    traverse(&folder.join("spec/call"), |_, res| res.is_err());
 }
 
 #[test]
 fn spec_closure() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/closure"), |res| res.is_ok());
+    traverse(&folder.join("spec/closure"), |_, res| res.is_ok());
 }
 
 #[test]
 fn spec_class() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/class"), |res| res.is_ok());
+    traverse(&folder.join("spec/class"), |_, res| res.is_ok());
 }
 
 #[test]
 fn spec_if() {
     let folder = get_test_folder();
-    traverse(&folder.join("spec/if"), |res| res.is_ok());
+    traverse(&folder.join("spec/if"), spec_outcome);
+}
+
+#[test]
+fn print_writes_the_bare_value() {
+    // the reference suite's `// expect:` lines hold exactly what `print` shows
+    assert_eq!(
+        run_captured("print 1; print \"a\"; print true;"),
+        "1\na\ntrue\n"
+    );
+}
+
+#[test]
+fn return_leaves_a_while_loop() {
+    // used to keep looping with the return value thrown away
+    let source = "fun f() { while (true) { return 1; } } print f();";
+    assert_eq!(run_captured(source), "1\n");
+}
+
+#[test]
+fn runtime_error_trace() {
+    let interpreter = Rc::new(RefCell::new(Interpreter::new()));
+    let source = "fun inner() {\n  return 1 + nil;\n}\nfun outer() {\n  inner();\n}\nouter();\n";
+    assert!(repl(Rc::clone(&interpreter), 0, source).is_err());
+
+    let trace = interpreter.borrow_mut().take_trace();
+    let lines: Vec<_> = trace
+        .iter()
+        .map(|frame| (frame.span.line, frame.function.as_deref()))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![(2, Some("inner")), (5, Some("outer")), (7, None)]
+    );
+    assert!(interpreter.borrow().frames.is_empty());
 }
@@ -1,59 +1,79 @@
 use std::fmt;
 
+use crate::Span;
+
 pub type Result<T, U = LoxErr> = std::result::Result<T, U>;
 
 #[derive(Debug, Clone)]
 pub enum LoxErr {
-    Undefined {
-        message: String,
-    },
-    Eval {
-        expr: String,
-        message: String,
-    },
-    Internal {
-        message: String,
-    },
-    Resolve {
-        message: String,
-    },
-    Parse {
-        token: String,
-        line: String,
-        column: String,
-    },
-    Scan {
-        line: i32,
-        col: i32,
-        message: String,
-    },
+    Undefined { message: String, span: Span },
+    Eval { message: String, span: Span },
+    Internal { message: String, span: Span },
+    Resolve { message: String, span: Span },
+    Parse { message: String, span: Span },
+    Scan { message: String, span: Span },
+}
+
+impl LoxErr {
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Undefined { message, .. }
+            | Self::Eval { message, .. }
+            | Self::Internal { message, .. }
+            | Self::Resolve { message, .. }
+            | Self::Parse { message, .. }
+            | Self::Scan { message, .. } => message,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::Undefined { span, .. }
+            | Self::Eval { span, .. }
+            | Self::Internal { span, .. }
+            | Self::Resolve { span, .. }
+            | Self::Parse { span, .. }
+            | Self::Scan { span, .. } => span,
+        }
+    }
+
+    /// Points an error that doesn't know where it happened yet at `span`,
+    /// leaving errors that already do as they are
+    pub fn or_at(mut self, at: &Span) -> Self {
+        match &mut self {
+            Self::Undefined { span, .. }
+            | Self::Eval { span, .. }
+            | Self::Internal { span, .. }
+            | Self::Resolve { span, .. }
+            | Self::Parse { span, .. }
+            | Self::Scan { span, .. } => {
+                if span.is_unknown() {
+                    *span = at.clone();
+                }
+            }
+        }
+        self
+    }
 }
 
 impl fmt::Display for LoxErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Parse { .. } => "Parsing error",
+            Self::Eval { .. } => "Eval error",
+            Self::Scan { .. } => "Syntax error",
+            Self::Internal { .. } => "Internal program error",
+            Self::Undefined { .. } => "Undefined error",
+            Self::Resolve { .. } => "Variable resolving error",
+        };
+        let span = self.span();
         write!(
             f,
-            "{}",
-            match self {
-                Self::Parse {
-                    token,
-                    line,
-                    column,
-                } => format!(
-                    "Parsing error for token: {}\nat line: {}, col: {}",
-                    token, line, column
-                ),
-                Self::Eval { expr, message } => {
-                    format!("Eval error: {}\n for expression of type: {}", expr, message)
-                }
-                Self::Scan { line, col, message } => {
-                    format!("Syntax error: {}\nat line: {}, col: {}", message, line, col)
-                }
-                Self::Internal { message } => format!("Internal program error: {}", message),
-                Self::Undefined { message } => format!("Undefined error: {}", message),
-
-                Self::Resolve { message } => format!("Variable resolving error: {}", message),
-            }
+            "{}: {}\nat line: {}, col: {}",
+            kind,
+            self.message(),
+            span.line,
+            span.column
         )
     }
 }
@@ -1,2 +1,4 @@
 pub mod error;
+pub mod span;
 pub use error::{LoxErr, Result};
+pub use span::Span;
@@ -0,0 +1,40 @@
+use std::ops::Range;
+
+/// Where a token, an expression or an error sits in the source
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// Which source the span points into, as numbered by whoever keeps the
+    /// sources around to render errors with. A REPL session has one per line
+    pub file: usize,
+    /// Byte offsets into the source
+    pub range: Range<usize>,
+    /// 1-based line of the start of the span, 0 when the location isn't known
+    pub line: usize,
+    /// 1-based column of the start of the span
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(file: usize, range: Range<usize>, line: usize, column: usize) -> Self {
+        Self {
+            file,
+            range,
+            line,
+            column,
+        }
+    }
+
+    /// Errors raised away from the source, e.g. by a scope lookup, don't know
+    /// where they happened until the code evaluating an expression tells them
+    pub fn is_unknown(&self) -> bool {
+        self.line == 0
+    }
+
+    /// Covers everything from the start of `self` to the end of `other`
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            range: self.range.start..other.range.end.max(self.range.end),
+            ..self.clone()
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Times the benchmark scripts from the e2e suite, once without and once with the
+//! optimizer. Run with `cargo bench -p loxrs_vm`, optionally followed by a filter
+//! on the script names, e.g. `cargo bench -p loxrs_vm -- fib`.
+//!
+//! Setting `LOXRS_BENCH_BASELINE` to the path of another `loxrs_vm` binary adds a
+//! column for it, which is how the numbers for a change are compared to before it.
+//! Every cell is the mean of `LOXRS_BENCH_RUNS` runs and their standard deviation,
+//! so that a difference can be told apart from noise.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+const BENCHMARK_DIRS: [&str; 2] = [
+    "../loxrs_interpreter/src/lox/interpreter/test/e2e/spec/benchmark",
+    "../loxrs_interpreter/src/lox/interpreter/test/e2e/unenforced/benchmark",
+];
+
+/// How many times each script runs, unless `LOXRS_BENCH_RUNS` says otherwise
+const DEFAULT_RUNS: usize = 5;
+
+fn main() {
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let vm = PathBuf::from(env!("CARGO_BIN_EXE_loxrs_vm"));
+    let baseline = env::var_os("LOXRS_BENCH_BASELINE").map(PathBuf::from);
+    let runs = env::var("LOXRS_BENCH_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .filter(|runs| *runs > 0)
+        .unwrap_or(DEFAULT_RUNS);
+
+    let mut columns = vec![];
+    if let Some(baseline) = &baseline {
+        columns.push(("baseline", baseline.as_path(), None));
+    }
+    columns.push(("opt 0", vm.as_path(), Some("0")));
+    columns.push(("opt 1", vm.as_path(), Some("1")));
+
+    print!("{:<24}", "benchmark");
+    for (name, ..) in &columns {
+        print!("{name:>18}");
+    }
+    println!();
+
+    for script in scripts() {
+        let name = script.file_stem().unwrap().to_string_lossy();
+        if filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            continue;
+        }
+        print!("{name:<24}");
+        for (_, binary, opt_level) in &columns {
+            match time(binary, &script, *opt_level, runs) {
+                Some((mean, deviation)) => print!("{:>18}", format!("{mean:.0}ms ±{deviation:.0}")),
+                None => print!("{:>18}", "failed"),
+            }
+        }
+        println!();
+    }
+}
+
+fn scripts() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut scripts: Vec<PathBuf> = BENCHMARK_DIRS
+        .iter()
+        .flat_map(|dir| fs::read_dir(root.join(dir)).expect("benchmark directory exists"))
+        .map(|entry| entry.expect("benchmark directory is readable").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+/// The mean and standard deviation in milliseconds of `runs` runs, or `None`
+/// if the script didn't run successfully
+fn time(binary: &Path, script: &Path, opt_level: Option<&str>, runs: usize) -> Option<(f64, f64)> {
+    let times = (0..runs)
+        .map(|_| {
+            let mut command = Command::new(binary);
+            command
+                .arg(script)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            if let Some(opt_level) = opt_level {
+                command.env("LOXRS_OPT_LEVEL", opt_level);
+            }
+
+            let start = Instant::now();
+            let status = command.status().ok()?;
+            status
+                .success()
+                .then(|| start.elapsed().as_secs_f64() * 1000.0)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let mean = times.iter().sum::<f64>() / runs as f64;
+    let variance = times.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / runs as f64;
+    Some((mean, variance.sqrt()))
+}
@@ -0,0 +1,406 @@
+//! Binary format for compiled scripts, so a `.lox` file can be compiled once
+//! and run later without scanning or compiling it again.
+//!
+//! Every number is little endian. A file is laid out as
+//!
+//! ```text
+//! magic     b"LOXC"
+//! version   u16
+//! source    str        kept so runtime errors can still point into it
+//! script    function
+//!
+//! function  name: u8 (0 = none, 1 = str follows), arity: u8, upvalue_count: u8,
+//!           code: u32 length + bytes,
+//!           constants: u32 count + constant*,
+//!           spans: u32 count + (offset: u32, start: u32, end: u32)*
+//! constant  u8 tag, followed by an f64, str or function depending on the tag
+//! str       u32 length + UTF-8 bytes
+//! ```
+
+use std::rc::Rc;
+
+use crate::{
+    config::MAX_FUNCTION_DEPTH,
+    entities::{
+        chunk::Chunk,
+        object::{ObjFunction, ObjKind},
+        value::Value,
+    },
+    error::{LoadError, Result as LoxResult},
+    heap::Heap,
+};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// bumped whenever the layout or the opcode numbering changes
+pub const VERSION: u16 = 1;
+
+const TAG_NIL: u8 = 0;
+const TAG_TRUE: u8 = 1;
+const TAG_FALSE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+pub fn serialize(source: &str, script: &ObjFunction) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    write_str(&mut bytes, source);
+    write_function(&mut bytes, script);
+    bytes
+}
+
+/// Allocates the script and everything in its constant pools on `heap`,
+/// returning the source it was compiled from along with the script function
+pub fn deserialize(bytes: &[u8], heap: &mut Heap) -> LoxResult<(String, Value), LoadError> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        source_len: 0,
+    };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(LoadError::InvalidMagic);
+    }
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version, VERSION));
+    }
+
+    let source = reader.str()?.to_owned();
+    reader.source_len = source.len();
+    let script = reader.function(heap, 1)?;
+    if reader.pos != bytes.len() {
+        return Err(LoadError::TrailingBytes);
+    }
+    Ok((source, script))
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend((value as u32).to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    write_u32(bytes, value.len());
+    bytes.extend(value.as_bytes());
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &ObjFunction) {
+    match &function.name {
+        Some(name) => {
+            bytes.push(1);
+            write_str(bytes, name);
+        }
+        None => bytes.push(0),
+    }
+    // both are capped at 255 by the compiler
+    bytes.push(function.arity as u8);
+    bytes.push(function.upvalue_count as u8);
+
+    let chunk = &function.chunk;
+    write_u32(bytes, chunk.code.len());
+    bytes.extend(&chunk.code);
+
+    write_u32(bytes, chunk.constants().len());
+    for constant in chunk.constants() {
+        write_constant(bytes, *constant);
+    }
+
+    write_u32(bytes, chunk.span_runs().count());
+    for (offset, span) in chunk.span_runs() {
+        write_u32(bytes, offset);
+        write_u32(bytes, span.start);
+        write_u32(bytes, span.end);
+    }
+}
+
+fn write_constant(bytes: &mut Vec<u8>, constant: Value) {
+    if constant.is_nil() {
+        bytes.push(TAG_NIL);
+    } else if constant.is_true() {
+        bytes.push(TAG_TRUE);
+    } else if constant.is_false() {
+        bytes.push(TAG_FALSE);
+    } else if let Ok(number) = constant.try_number() {
+        bytes.push(TAG_NUMBER);
+        bytes.extend(number.to_le_bytes());
+    } else if let Some(str) = constant.as_string() {
+        bytes.push(TAG_STRING);
+        write_str(bytes, &str.value);
+    } else if let Some(function) = constant.as_obj().and_then(|obj| obj.as_function()) {
+        bytes.push(TAG_FUNCTION);
+        write_function(bytes, function);
+    } else {
+        unreachable!("the compiler only stores literals and functions as constants")
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+    /// every span has to point into the embedded source
+    source_len: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> LoxResult<&'b [u8], LoadError> {
+        let end = self.pos.checked_add(len).ok_or(LoadError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(LoadError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> LoxResult<[u8; N], LoadError> {
+        Ok(self
+            .take(N)?
+            .try_into()
+            .expect("`take` returns exactly N bytes"))
+    }
+
+    fn u8(&mut self) -> LoxResult<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> LoxResult<usize, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn str(&mut self) -> LoxResult<&'b str, LoadError> {
+        let len = self.u32()?;
+        std::str::from_utf8(self.take(len)?).map_err(|_| LoadError::InvalidString)
+    }
+
+    /// `depth` counts the script as 1, and is capped so that a file nesting
+    /// functions without end can't overflow the stack
+    fn function(&mut self, heap: &mut Heap, depth: usize) -> LoxResult<Value, LoadError> {
+        if depth > MAX_FUNCTION_DEPTH {
+            return Err(LoadError::TooDeep(MAX_FUNCTION_DEPTH));
+        }
+        let name = match self.u8()? {
+            0 => None,
+            _ => Some(Rc::from(self.str()?)),
+        };
+        let mut function = ObjFunction::new(name);
+        function.arity = self.u8()? as usize;
+        function.upvalue_count = self.u8()? as usize;
+
+        let code_len = self.u32()?;
+        let code = self.take(code_len)?.to_vec();
+
+        // counts come from the file, so they aren't trusted to preallocate
+        let constant_count = self.u32()?;
+        let mut constants = vec![];
+        for _ in 0..constant_count {
+            constants.push(self.constant(heap, depth)?);
+        }
+
+        let span_count = self.u32()?;
+        let mut spans: Vec<(usize, _)> = vec![];
+        for _ in 0..span_count {
+            let offset = self.u32()?;
+            // lookups binary search the runs by their offset
+            let sorted = spans.last().is_none_or(|(prev, _)| *prev < offset);
+            if !sorted || offset >= code_len {
+                return Err(LoadError::InvalidSpanOffset(offset));
+            }
+            let start = self.u32()?;
+            let end = self.u32()?;
+            if start > end || end > self.source_len {
+                return Err(LoadError::InvalidSpan(start..end));
+            }
+            spans.push((offset, start..end));
+        }
+
+        function.chunk = Chunk::from_parts(code, constants, spans);
+        Ok(heap.alloc(ObjKind::Function(function)))
+    }
+
+    fn constant(&mut self, heap: &mut Heap, depth: usize) -> LoxResult<Value, LoadError> {
+        match self.u8()? {
+            TAG_NIL => Ok(Value::NIL),
+            TAG_TRUE => Ok(Value::TRUE),
+            TAG_FALSE => Ok(Value::FALSE),
+            TAG_NUMBER => {
+                // a NaN can carry the bits of any other value, including an object pointer
+                let bits = u64::from_le_bytes(self.array()?);
+                let number = Value::from(f64::from_bits(bits));
+                match number.is_number() {
+                    true => Ok(number),
+                    false => Err(LoadError::InvalidNumber(bits)),
+                }
+            }
+            TAG_STRING => {
+                let str = self.str()?;
+                Ok(heap.intern(str))
+            }
+            TAG_FUNCTION => self.function(heap, depth + 1),
+            tag => Err(LoadError::UnknownConstant(tag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compiler::{compile, Mode},
+        entities::{opcode, table::Table},
+        verifier::verify,
+    };
+
+    fn load(bytes: &[u8]) -> LoxResult<(), LoadError> {
+        deserialize(bytes, &mut Heap::new()).map(|_| ())
+    }
+
+    /// A function returning its only constant, with a span run starting at each of `spans`
+    fn function(constant: &[u8], spans: &[usize]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0];
+        write_u32(&mut bytes, 3);
+        bytes.extend([opcode::CONSTANT, 0, opcode::RETURN]);
+        write_u32(&mut bytes, 1);
+        bytes.extend(constant);
+        write_u32(&mut bytes, spans.len());
+        for offset in spans {
+            write_u32(&mut bytes, *offset);
+            write_u32(&mut bytes, 0);
+            write_u32(&mut bytes, 3);
+        }
+        bytes
+    }
+
+    fn script(constant: &[u8], spans: &[usize]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        write_str(&mut bytes, "nil;");
+        bytes.extend(function(constant, spans));
+        bytes
+    }
+
+    /// The constant of a script nesting `depth` functions, counting the script
+    fn nested(depth: usize) -> Vec<u8> {
+        (1..depth).fold(vec![TAG_NIL], |constant, _| {
+            let mut bytes = vec![TAG_FUNCTION];
+            bytes.extend(function(&constant, &[]));
+            bytes
+        })
+    }
+
+    fn number(bits: u64) -> Vec<u8> {
+        let mut bytes = vec![TAG_NUMBER];
+        bytes.extend(bits.to_le_bytes());
+        bytes
+    }
+
+    const SOURCE: &str = "
+        var greeting = \"hello\";
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1.5; return count; }
+            return increment;
+        }
+        class Base { greet() { return \"hi \"; } }
+        class Greeter < Base { greet() { return super.greet() + greeting; } }
+        print counter()() > 1 and !nil;
+    ";
+
+    #[test]
+    fn round_trips_a_compiled_script() {
+        let mut heap = Heap::new();
+        let script = compile(SOURCE, 0, &mut heap, &Table::default(), 1, Mode::File).unwrap();
+        let script = script.as_obj().and_then(|obj| obj.as_function()).unwrap();
+        let bytes = serialize(SOURCE, script);
+
+        let (source, loaded) = deserialize(&bytes, &mut heap).unwrap();
+        let loaded = loaded.as_obj().and_then(|obj| obj.as_function()).unwrap();
+        assert_eq!(source, SOURCE);
+        assert_eq!(serialize(&source, loaded), bytes);
+        assert!(verify(loaded).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let mut heap = Heap::new();
+        let script = compile(SOURCE, 0, &mut heap, &Table::default(), 1, Mode::File).unwrap();
+        let script = script.as_obj().and_then(|obj| obj.as_function()).unwrap();
+        let bytes = serialize(SOURCE, script);
+
+        for len in 0..bytes.len() {
+            assert_eq!(
+                load(&bytes[..len]),
+                Err(LoadError::Truncated),
+                "cut at {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let valid = script(&[TAG_NIL], &[0]);
+        assert_eq!(load(&valid), Ok(()));
+
+        let mut magic = valid.clone();
+        magic[0] = b'X';
+        assert_eq!(load(&magic), Err(LoadError::InvalidMagic));
+
+        let mut version = valid.clone();
+        version[MAGIC.len()] = 0xff;
+        assert!(matches!(
+            load(&version),
+            Err(LoadError::UnsupportedVersion(_, VERSION))
+        ));
+
+        let mut trailing = valid.clone();
+        trailing.push(0);
+        assert_eq!(load(&trailing), Err(LoadError::TrailingBytes));
+
+        assert_eq!(
+            load(&script(&[0xff], &[])),
+            Err(LoadError::UnknownConstant(0xff))
+        );
+        assert_eq!(
+            load(&script(&[TAG_STRING, 1, 0, 0, 0, 0xff], &[])),
+            Err(LoadError::InvalidString)
+        );
+    }
+
+    #[test]
+    fn rejects_numbers_that_decode_as_other_values() {
+        // an object pointer, `nil` and `true` in turn
+        for bits in [
+            0xFFFC_0000_DEAD_BEEF,
+            0x7FFC_0000_0000_0001,
+            0x7FFC_0000_0000_0002,
+        ] {
+            assert_eq!(
+                load(&script(&number(bits), &[])),
+                Err(LoadError::InvalidNumber(bits))
+            );
+        }
+        // NaN itself is still a number
+        assert_eq!(load(&script(&number(f64::NAN.to_bits()), &[])), Ok(()));
+    }
+
+    #[test]
+    fn rejects_span_runs_out_of_order_or_past_the_code() {
+        assert_eq!(load(&script(&[TAG_NIL], &[0, 2])), Ok(()));
+        assert_eq!(
+            load(&script(&[TAG_NIL], &[0, 3])),
+            Err(LoadError::InvalidSpanOffset(3))
+        );
+        assert_eq!(
+            load(&script(&[TAG_NIL], &[2, 1])),
+            Err(LoadError::InvalidSpanOffset(1))
+        );
+        assert_eq!(
+            load(&script(&[TAG_NIL], &[1, 1])),
+            Err(LoadError::InvalidSpanOffset(1))
+        );
+    }
+
+    #[test]
+    fn rejects_functions_nested_too_deep() {
+        assert_eq!(load(&script(&nested(MAX_FUNCTION_DEPTH), &[])), Ok(()));
+        assert_eq!(
+            load(&script(&nested(MAX_FUNCTION_DEPTH + 1), &[])),
+            Err(LoadError::TooDeep(MAX_FUNCTION_DEPTH))
+        );
+    }
+}
@@ -1,45 +1,811 @@
-use std::ops::Range;
+use std::{ops::Range, rc::Rc};
 
 use log::{debug, trace};
 
 use crate::{
+    config::{MAX_ARGS, MAX_FUNCTION_DEPTH, MAX_LOCALS, MAX_UPVALUES},
     constants::NO_SPAN,
-    entities::{chunk::Chunk, opcode, precedence, value::Value},
-    error::{CompilerError, InternalError, LoxErrorS, Result},
+    entities::{
+        chunk::Chunk,
+        object::{ObjFunction, ObjKind},
+        opcode, precedence,
+        table::Table,
+        value::Value,
+    },
+    error::{CompilerError, InternalError, LoxErrorS, OverflowError, Result, SyntaxError},
+    heap::Heap,
+    optimizer,
     parser::Parser,
     scanner::{scan, Token, TokenS},
     types::Span,
 };
 
-pub fn compile(source: &str) -> Result<Chunk, Vec<LoxErrorS>> {
-    let mut compiler = Compiler::new(source)?;
-    compiler.compile()?;
-    Ok(compiler.chunk)
+/// Compiles `source` into the top-level script function, allocated on `heap`.
+/// Every function compiled from it records `file` as the source its spans point
+/// into. `globals` are only read as roots for any collection during compilation,
+/// and every function is run through the optimizer unless `opt_level` is `0`
+pub fn compile<'h>(
+    source: &str,
+    file: usize,
+    heap: &'h mut Heap,
+    globals: &'h Table,
+    opt_level: u8,
+    mode: Mode,
+) -> Result<Value, Vec<LoxErrorS>> {
+    let mut compiler = Compiler::new(source, file, heap, globals, opt_level, mode)?;
+    compiler.compile()
 }
 
-struct Compiler {
-    chunk: Chunk,
+/// Where the source being compiled comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    File,
+    /// a line typed into the REPL, which echoes the value
+    /// of every expression statement at the top level
+    Repl,
+}
+
+struct Compiler<'h> {
     parser: Parser,
+    heap: &'h mut Heap,
+    globals: &'h Table,
+    /// one entry per function being compiled, innermost last
+    states: Vec<FunctionState>,
+    /// one entry per class declaration being compiled, innermost last
+    classes: Vec<ClassState>,
+    errors: Vec<LoxErrorS>,
+    file: usize,
+    opt_level: u8,
+    mode: Mode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Everything tracked while compiling a single function body
+#[derive(Debug)]
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
 }
 
-impl Compiler {
-    fn new(source: &str) -> Result<Self, Vec<LoxErrorS>> {
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<Rc<str>>, file: usize) -> Self {
+        let mut locals = Vec::with_capacity(MAX_LOCALS);
+        // slot zero holds the function being called, or the receiver for methods
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        locals.push(Local {
+            name: slot_zero.to_owned(),
+            depth: Some(0),
+            is_captured: false,
+        });
+
+        let mut function = ObjFunction::new(name);
+        function.file = file;
+        Self {
+            function,
+            kind,
+            locals,
+            upvalues: vec![],
+            scope_depth: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// A local variable living in a stack slot. `depth` is left empty while
+/// the variable is declared but its initializer hasn't finished compiling
+#[derive(Debug, Clone)]
+struct Local {
+    name: String,
+    depth: Option<usize>,
+    /// captured locals are moved off the stack when they go out of scope
+    is_captured: bool,
+}
+
+/// A variable captured from an enclosing function, either straight from
+/// its stack slot or from one of the enclosing function's own upvalues
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+impl<'h> Compiler<'h> {
+    fn new(
+        source: &str,
+        file: usize,
+        heap: &'h mut Heap,
+        globals: &'h Table,
+        opt_level: u8,
+        mode: Mode,
+    ) -> Result<Self, Vec<LoxErrorS>> {
         let parser = Parser::new(scan(source)?);
         Ok(Self {
-            chunk: Chunk::new(),
             parser,
+            heap,
+            globals,
+            states: vec![FunctionState::new(FunctionKind::Script, None, file)],
+            classes: vec![],
+            errors: vec![],
+            file,
+            opt_level,
+            mode,
         })
     }
 
-    fn compile(&mut self) -> Result<&Chunk, Vec<LoxErrorS>> {
-        if let Err(e) = self.expression() {
-            return Err(vec![e]);
+    fn compile(&mut self) -> Result<Value, Vec<LoxErrorS>> {
+        while !self.parser.is_at_end() {
+            self.declaration(true);
+        }
+
+        match self.end_function() {
+            Ok((script, _)) if self.errors.is_empty() => Ok(script),
+            Ok(_) => Err(std::mem::take(&mut self.errors)),
+            Err(e) => {
+                self.errors.push(e);
+                Err(std::mem::take(&mut self.errors))
+            }
+        }
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states
+            .last()
+            .expect("the script state is only popped once compilation ends")
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states
+            .last_mut()
+            .expect("the script state is only popped once compilation ends")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state_mut().function.chunk
+    }
+
+    fn intern(&mut self, value: &str) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(value)
+    }
+
+    /// Functions still being compiled aren't on the heap yet,
+    /// so their constants are roots in their own right
+    fn collect_garbage(&mut self) {
+        for state in &self.states {
+            for constant in state.function.chunk.constants() {
+                self.heap.mark_value(*constant);
+            }
+        }
+        self.heap.mark_table(self.globals);
+        self.heap.collect();
+    }
+
+    /// Finishes the innermost function and moves it onto the heap,
+    /// along with the variables it captures
+    fn end_function(&mut self) -> Result<(Value, Vec<Upvalue>), LoxErrorS> {
+        self.emit_return()?;
+        // collect while the function's constants are still reachable from `states`
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+
+        let mut state = self
+            .states
+            .pop()
+            .ok_or((InternalError::UnexpectedCodePath.into(), NO_SPAN))?;
+        state.function.upvalue_count = state.upvalues.len();
+        if self.opt_level > 0 {
+            optimizer::optimize(&mut state.function.chunk).map_err(|e| (e, NO_SPAN))?;
+        }
+
+        trace!("compiled {}: {}", state.function, state.function.chunk);
+        let function = self.heap.alloc(ObjKind::Function(state.function));
+        Ok((function, state.upvalues))
+    }
+
+    /// Skips tokens until a likely statement boundary, so that
+    /// a single error doesn't cascade into several unrelated ones
+    fn synchronize(&mut self) {
+        trace!("calling synchronize()");
+        while !self.parser.is_at_end() {
+            if let Some((Token::Semicolon, _)) = self.parser.prev {
+                return;
+            }
+
+            if let Some((
+                Token::Class
+                | Token::Fun
+                | Token::Var
+                | Token::For
+                | Token::If
+                | Token::While
+                | Token::Print
+                | Token::Return,
+                _,
+            )) = self.parser.curr
+            {
+                return;
+            }
+            self.parser.advance();
+        }
+    }
+
+    /// Errors are recorded here rather than propagated, so that compilation
+    /// resumes at the next statement and reports every error in one pass.
+    /// `top_level` is only set for the declarations making up the script itself
+    fn declaration(&mut self, top_level: bool) {
+        trace!("calling declaration()");
+        // `fun` without a name starts an anonymous function expression instead
+        let res = if self.parser.check(&Token::Fun)
+            && self.parser.check_next(&Token::Literal(String::new()))
+        {
+            self.parser.advance();
+            self.fun_declaration()
+        } else if self.parser.matches(Token::Class) {
+            self.class_declaration()
+        } else if self.parser.matches(Token::Var) {
+            self.var_declaration()
+        } else {
+            self.statement(top_level)
+        };
+
+        if let Err(e) = res {
+            self.errors.push(e);
+            self.synchronize();
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling class_declaration()");
+        let (name, span) = self.parser.consume_identifier("Expected class name")?;
+        // unlike `parse_variable`, the name constant is needed even for local classes
+        let name_constant = self.identifier_constant(&name, &span)?;
+        if self.state().scope_depth > 0 {
+            self.declare_variable(name.clone(), &span)?;
+        }
+
+        self.emit_indexed(opcode::CLASS, name_constant, &span)?;
+        self.define_variable(name_constant, span.clone())?;
+
+        self.classes.push(ClassState::default());
+        let res = self.class_body(&name, span);
+        // closes the scope holding `super`, even if the body failed to compile
+        if self.classes.pop().is_some_and(|class| class.has_superclass) {
+            self.end_scope()?;
+        }
+        res
+    }
+
+    fn class_body(&mut self, name: &str, span: Range<usize>) -> Result<(), LoxErrorS> {
+        if self.parser.matches(Token::Less) {
+            self.superclass(name)?;
+        }
+
+        // methods are bound to the class, so it has to be on the stack
+        self.named_variable(name, span.clone(), false)?;
+        self.parser
+            .consume(Token::LeftBrace, "Expected `{` before class body")?;
+
+        while !self.parser.check(&Token::RightBrace) && !self.parser.is_at_end() {
+            self.method()?;
+        }
+
+        self.parser
+            .consume(Token::RightBrace, "Expected `}` after class body")?;
+        self.emit_byte((opcode::POP, span))
+    }
+
+    /// Leaves the superclass in a scope around the class body as a local
+    /// named `super`, which methods capture to resolve `super` calls
+    fn superclass(&mut self, name: &str) -> Result<(), LoxErrorS> {
+        let (superclass, span) = self.parser.consume_identifier("Expected superclass name")?;
+        if superclass == name {
+            return Err((CompilerError::InheritFromSelf.into(), span));
+        }
+        self.named_variable(&superclass, span.clone(), false)?;
+
+        self.begin_scope();
+        self.declare_variable("super".to_owned(), &span)?;
+        self.define_variable(0, span.clone())?;
+        if let Some(class) = self.classes.last_mut() {
+            class.has_superclass = true;
+        }
+
+        self.named_variable(name, span.clone(), false)?;
+        self.emit_byte((opcode::INHERIT, span))
+    }
+
+    fn method(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling method()");
+        let (name, span) = self.parser.consume_identifier("Expected method name")?;
+        let name_constant = self.identifier_constant(&name, &span)?;
+
+        let kind = match name.as_str() {
+            "init" => FunctionKind::Initializer,
+            _ => FunctionKind::Method,
+        };
+        self.function(kind, Rc::from(name), span.clone())?;
+
+        self.emit_indexed(opcode::METHOD, name_constant, &span)
+    }
+
+    fn fun_declaration(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling fun_declaration()");
+        let (global, span) = self.parse_variable("Expected function name")?;
+        let name = match &self.parser.prev {
+            Some((Token::Literal(name), _)) => Rc::from(name.as_str()),
+            _ => return Err((InternalError::UnexpectedCodePath.into(), span)),
+        };
+
+        // a function may refer to itself, so its name is usable right away
+        self.mark_initialized();
+        self.function(FunctionKind::Function, name, span.clone())?;
+        self.define_variable(global, span)
+    }
+
+    /// `fun (params) { body }` used as an expression
+    fn lambda(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling lambda()");
+        let span = self.prev_span();
+        self.function(FunctionKind::Function, Rc::from("anonymous"), span)
+    }
+
+    fn function(
+        &mut self,
+        kind: FunctionKind,
+        name: Rc<str>,
+        span: Range<usize>,
+    ) -> Result<(), LoxErrorS> {
+        trace!("calling function()");
+        // the loader and the verifier recurse into nested functions, so
+        // anything compiled has to stay within what they accept
+        if self.states.len() >= MAX_FUNCTION_DEPTH {
+            return Err((
+                OverflowError::ExceedsFunctionDepth(MAX_FUNCTION_DEPTH).into(),
+                span,
+            ));
+        }
+        self.states
+            .push(FunctionState::new(kind, Some(name), self.file));
+        let res = self.function_body();
+        let (function, upvalues) = self.end_function()?;
+        res?;
+
+        self.chunk()
+            .add_constant(opcode::CLOSURE, function, &span)?;
+        for upvalue in upvalues {
+            self.emit_byte((upvalue.is_local as u8, span.clone()))?;
+            self.emit_byte((upvalue.index, span.clone()))?;
+        }
+        Ok(())
+    }
+
+    fn function_body(&mut self) -> Result<(), LoxErrorS> {
+        self.begin_scope();
+        self.parser.consume(
+            Token::LeftParen,
+            "Expected `(` after function name or `fun`",
+        )?;
+
+        if !self.parser.check(&Token::RightParen) {
+            loop {
+                if self.state().function.arity >= MAX_ARGS {
+                    return Err((
+                        OverflowError::ExceedsParameterCount(MAX_ARGS).into(),
+                        self.curr_span(),
+                    ));
+                }
+                self.state_mut().function.arity += 1;
+
+                let (param, span) = self.parse_variable("Expected parameter name")?;
+                self.define_variable(param, span)?;
+
+                if !self.parser.matches(Token::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.parser
+            .consume(Token::RightParen, "Expected `)` after parameters")?;
+        self.parser
+            .consume(Token::LeftBrace, "Expected `{` before function body")?;
+        self.block()
+    }
+
+    fn var_declaration(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling var_declaration()");
+        let (global, span) = self.parse_variable("Expected variable name")?;
+
+        if self.parser.matches(Token::Equal) {
+            self.expression()?;
+        } else {
+            self.emit_constant(Value::NIL, &span)?;
+        }
+        self.parser
+            .consume(Token::Semicolon, "Expected `;` after variable declaration")?;
+
+        self.define_variable(global, span)
+    }
+
+    fn parse_variable(&mut self, err: &str) -> Result<Span<usize>, LoxErrorS> {
+        let (name, span) = self.parser.consume_identifier(err)?;
+
+        if self.state().scope_depth > 0 {
+            self.declare_variable(name, &span)?;
+            // locals are resolved to stack slots, so there's no constant to refer to
+            return Ok((0, span));
+        }
+        Ok((self.identifier_constant(&name, &span)?, span))
+    }
+
+    fn declare_variable(&mut self, name: String, span: &Range<usize>) -> Result<(), LoxErrorS> {
+        let state = self.state();
+        let redeclared = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= state.scope_depth))
+            .any(|local| local.name == name);
+
+        if redeclared {
+            return Err((CompilerError::AlreadyDeclared(name).into(), span.clone()));
+        }
+
+        if state.locals.len() >= MAX_LOCALS {
+            return Err((
+                OverflowError::ExceedsLocalSize(MAX_LOCALS).into(),
+                span.clone(),
+            ));
+        }
+
+        self.state_mut().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(state.scope_depth);
+        }
+    }
+
+    /// `state` indexes into `self.states`, so that enclosing functions can be searched too
+    fn resolve_local(
+        &self,
+        state: usize,
+        name: &str,
+        span: &Range<usize>,
+    ) -> Result<Option<u8>, LoxErrorS> {
+        match self.states[state]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name)
+        {
+            Some((_, Local { depth: None, .. })) => Err((
+                CompilerError::ReadInOwnInitializer(name.to_owned()).into(),
+                span.clone(),
+            )),
+            // `MAX_LOCALS` guarantees slots fit in a byte
+            Some((slot, _)) => Ok(Some(slot as u8)),
+            None => Ok(None),
+        }
+    }
+
+    fn resolve_upvalue(
+        &mut self,
+        state: usize,
+        name: &str,
+        span: &Range<usize>,
+    ) -> Result<Option<u8>, LoxErrorS> {
+        // the script has no enclosing function, so its variables are globals
+        let Some(enclosing) = state.checked_sub(1) else {
+            return Ok(None);
+        };
+
+        if let Some(slot) = self.resolve_local(enclosing, name, span)? {
+            self.states[enclosing].locals[slot as usize].is_captured = true;
+            let upvalue = Upvalue {
+                index: slot,
+                is_local: true,
+            };
+            return self.add_upvalue(state, upvalue, span).map(Some);
+        }
+
+        match self.resolve_upvalue(enclosing, name, span)? {
+            Some(index) => {
+                let upvalue = Upvalue {
+                    index,
+                    is_local: false,
+                };
+                self.add_upvalue(state, upvalue, span).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        state: usize,
+        upvalue: Upvalue,
+        span: &Range<usize>,
+    ) -> Result<u8, LoxErrorS> {
+        let upvalues = &mut self.states[state].upvalues;
+        // `MAX_UPVALUES` guarantees indices fit in a byte
+        if let Some(idx) = upvalues.iter().position(|el| *el == upvalue) {
+            return Ok(idx as u8);
+        }
+
+        if upvalues.len() >= MAX_UPVALUES {
+            return Err((
+                OverflowError::ExceedsUpvalueSize(MAX_UPVALUES).into(),
+                span.clone(),
+            ));
+        }
+
+        upvalues.push(upvalue);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn identifier_constant(&mut self, name: &str, span: &Range<usize>) -> Result<usize, LoxErrorS> {
+        let name = self.intern(name);
+        self.chunk()
+            .add_identifier(name)
+            .map_err(|e| (e, span.clone()))
+    }
+
+    fn define_variable(&mut self, global: usize, span: Range<usize>) -> Result<(), LoxErrorS> {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+            return Ok(());
+        }
+
+        self.emit_indexed(opcode::DEFINE_GLOBAL, global, &span)
+    }
+
+    fn statement(&mut self, top_level: bool) -> Result<(), LoxErrorS> {
+        trace!("calling statement()");
+        if self.parser.matches(Token::Print) {
+            return self.print_statement();
+        }
+        if self.parser.matches(Token::Return) {
+            return self.return_statement();
+        }
+        if self.parser.matches(Token::If) {
+            return self.if_statement();
+        }
+        if self.parser.matches(Token::While) {
+            return self.while_statement();
+        }
+        if self.parser.matches(Token::For) {
+            return self.for_statement();
+        }
+        if self.parser.matches(Token::LeftBrace) {
+            self.begin_scope();
+            let res = self.block();
+            self.end_scope()?;
+            return res;
+        }
+        // only a line's own expression statements echo, not the body of its `if` or loop
+        self.expression_statement(top_level && self.mode == Mode::Repl)
+    }
+
+    fn if_statement(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling if_statement()");
+        let span = self.prev_span();
+        self.parser
+            .consume(Token::LeftParen, "Expected `(` after `if`")?;
+        self.expression()?;
+        self.parser
+            .consume(Token::RightParen, "Expected `)` after condition")?;
+
+        let then_jump = self.emit_jump((opcode::JUMP_IF_FALSE, span.clone()))?;
+        self.emit_byte((opcode::POP, span.clone()))?;
+        self.statement(false)?;
+
+        let else_jump = self.emit_jump((opcode::JUMP, span.clone()))?;
+        self.patch_jump(then_jump, &span)?;
+        self.emit_byte((opcode::POP, span.clone()))?;
+
+        if self.parser.matches(Token::Else) {
+            self.statement(false)?;
         }
+        self.patch_jump(else_jump, &span)
+    }
 
-        match self.emit_return() {
-            Ok(_) => Ok(&self.chunk),
-            Err(e) => return Err(vec![e]),
+    fn while_statement(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling while_statement()");
+        let span = self.prev_span();
+        let loop_start = self.chunk().code.len();
+        self.parser
+            .consume(Token::LeftParen, "Expected `(` after `while`")?;
+        self.expression()?;
+        self.parser
+            .consume(Token::RightParen, "Expected `)` after condition")?;
+
+        let exit_jump = self.emit_jump((opcode::JUMP_IF_FALSE, span.clone()))?;
+        self.emit_byte((opcode::POP, span.clone()))?;
+        self.statement(false)?;
+        self.emit_loop(loop_start, &span)?;
+
+        self.patch_jump(exit_jump, &span)?;
+        self.emit_byte((opcode::POP, span))
+    }
+
+    fn for_statement(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling for_statement()");
+        self.begin_scope();
+        let res = self.for_clauses();
+        self.end_scope()?;
+        res
+    }
+
+    /// Desugars into the equivalent `while` loop, with the increment clause
+    /// jumped over on entry and looped back to at the end of every iteration
+    fn for_clauses(&mut self) -> Result<(), LoxErrorS> {
+        let span = self.prev_span();
+        self.parser
+            .consume(Token::LeftParen, "Expected `(` after `for`")?;
+
+        if self.parser.matches(Token::Semicolon) {
+            // no initializer
+        } else if self.parser.matches(Token::Var) {
+            self.var_declaration()?;
+        } else {
+            self.expression_statement(false)?;
         }
+
+        let mut loop_start = self.chunk().code.len();
+        let mut exit_jump = None;
+
+        if !self.parser.matches(Token::Semicolon) {
+            self.expression()?;
+            self.parser
+                .consume(Token::Semicolon, "Expected `;` after loop condition")?;
+
+            exit_jump = Some(self.emit_jump((opcode::JUMP_IF_FALSE, span.clone()))?);
+            self.emit_byte((opcode::POP, span.clone()))?;
+        }
+
+        if !self.parser.matches(Token::RightParen) {
+            let body_jump = self.emit_jump((opcode::JUMP, span.clone()))?;
+            let increment_start = self.chunk().code.len();
+
+            self.expression()?;
+            self.emit_byte((opcode::POP, span.clone()))?;
+            self.parser
+                .consume(Token::RightParen, "Expected `)` after for clauses")?;
+
+            self.emit_loop(loop_start, &span)?;
+            loop_start = increment_start;
+            self.patch_jump(body_jump, &span)?;
+        }
+
+        self.statement(false)?;
+        self.emit_loop(loop_start, &span)?;
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, &span)?;
+            self.emit_byte((opcode::POP, span))?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling block()");
+        while !self.parser.check(&Token::RightBrace) && !self.parser.is_at_end() {
+            self.declaration(false);
+        }
+        self.parser
+            .consume(Token::RightBrace, "Expected `}` after block")
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) -> Result<(), LoxErrorS> {
+        self.state_mut().scope_depth -= 1;
+
+        let span = self.prev_span();
+        while let Some(local) = self.state().locals.last().filter(|local| {
+            local
+                .depth
+                .is_none_or(|depth| depth > self.state().scope_depth)
+        }) {
+            let op = match local.is_captured {
+                true => opcode::CLOSE_UPVALUE,
+                false => opcode::POP,
+            };
+            self.emit_byte((op, span.clone()))?;
+            self.state_mut().locals.pop();
+        }
+        Ok(())
+    }
+
+    fn print_statement(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling print_statement()");
+        let span = self.prev_span();
+        self.expression()?;
+        self.parser
+            .consume(Token::Semicolon, "Expected `;` after value")?;
+        self.emit_byte((opcode::PRINT, span))
+    }
+
+    fn return_statement(&mut self) -> Result<(), LoxErrorS> {
+        trace!("calling return_statement()");
+        let span = self.prev_span();
+        if self.state().kind == FunctionKind::Script {
+            return Err((CompilerError::ReturnFromTopLevel.into(), span));
+        }
+
+        if self.parser.matches(Token::Semicolon) {
+            return self.emit_return();
+        }
+
+        if self.state().kind == FunctionKind::Initializer {
+            return Err((CompilerError::ReturnFromInitializer.into(), span));
+        }
+
+        self.expression()?;
+        self.parser
+            .consume(Token::Semicolon, "Expected `;` after return value")?;
+        self.emit_byte((opcode::RETURN, span))
+    }
+
+    fn expression_statement(&mut self, echo: bool) -> Result<(), LoxErrorS> {
+        trace!("calling expression_statement()");
+        self.expression()?;
+        let span = self.curr_span();
+        self.parser
+            .consume(Token::Semicolon, "Expected `;` after expression")?;
+
+        match echo {
+            true => self.emit_byte((opcode::PRINT, span)),
+            false => self.emit_byte((opcode::POP, span)),
+        }
+    }
+
+    fn prev_span(&self) -> Range<usize> {
+        self.parser
+            .prev
+            .as_ref()
+            .map_or(NO_SPAN, |(_, span)| span.clone())
+    }
+
+    fn curr_span(&self) -> Range<usize> {
+        self.parser
+            .curr
+            .as_ref()
+            .map_or(NO_SPAN, |(_, span)| span.clone())
     }
 
     fn expression(&mut self) -> Result<(), LoxErrorS> {
@@ -47,14 +813,14 @@ impl Compiler {
         self.parse_precedence(precedence::PREC_ASSIGNMENT)
     }
 
-    fn grouping(&mut self) -> Result<(), LoxErrorS> {
+    fn grouping(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
         trace!("calling grouping()");
         self.expression()?;
         self.parser
             .consume(Token::RightParen, "Expected `)` after expression")
     }
 
-    fn ternary(&mut self) -> Result<(), LoxErrorS> {
+    fn ternary(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
         trace!("calling ternary()");
 
         let ternary = self.parser.prev.clone();
@@ -79,8 +845,13 @@ impl Compiler {
         let prefix_rule = {
             let prev = self.parser.prev.as_ref();
             match prev {
-                None => return Ok(()),
-                Some((Token::EndOfFile, _)) => return Ok(()),
+                None => return Err((InternalError::UnexpectedCodePath.into(), NO_SPAN)),
+                Some((Token::EndOfFile, span)) => {
+                    return Err((
+                        SyntaxError::UnexpectedValue("Expected expression".to_owned()).into(),
+                        span.clone(),
+                    ))
+                }
                 Some(token_s) => {
                     debug!("getting `prefix_rule` for: {:?}", prev);
                     get_rule(token_s)?.prefix.ok_or_else(|| {
@@ -93,7 +864,8 @@ impl Compiler {
             }
         };
 
-        prefix_rule(self)?;
+        let can_assign = prec <= precedence::PREC_ASSIGNMENT;
+        prefix_rule(self, can_assign)?;
 
         loop {
             let should_break = {
@@ -137,12 +909,19 @@ impl Compiler {
                 }
             };
 
-            infix_rule(self)?;
+            infix_rule(self, can_assign)?;
+        }
+
+        if can_assign && self.parser.check(&Token::Equal) {
+            return Err((
+                SyntaxError::UnexpectedValue("Invalid assignment target".to_owned()).into(),
+                self.curr_span(),
+            ));
         }
         Ok(())
     }
 
-    fn unary(&mut self) -> Result<(), LoxErrorS> {
+    fn unary(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
         trace!("calling unary()");
         let unary_kind = self.parser.prev.clone();
 
@@ -164,7 +943,7 @@ impl Compiler {
         }
     }
 
-    fn binary(&mut self) -> Result<(), LoxErrorS> {
+    fn binary(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
         trace!("calling binary()");
         let binary_kind = self.parser.prev.clone();
         let precedence = {
@@ -206,27 +985,246 @@ impl Compiler {
     }
 
     fn emit_byte(&mut self, byte: Span<u8>) -> Result<(), LoxErrorS> {
-        self.chunk.write_chunk(byte.0, byte.1);
+        self.chunk().write_chunk(byte.0, byte.1);
         Ok(())
     }
 
+    /// Emits a jump with a placeholder offset, returning the offset's
+    /// position for `patch_jump` once the jump target is known
+    fn emit_jump(&mut self, byte: Span<u8>) -> Result<usize, LoxErrorS> {
+        let span = byte.1.clone();
+        self.emit_byte(byte)?;
+        self.emit_byte((0xff, span.clone()))?;
+        self.emit_byte((0xff, span))?;
+        Ok(self.chunk().code.len() - 2)
+    }
+
+    fn patch_jump(&mut self, offset: usize, span: &Range<usize>) -> Result<(), LoxErrorS> {
+        self.chunk()
+            .patch_jump(offset)
+            .map_err(|e| (e, span.clone()))
+    }
+
+    /// Jumps back to `loop_start` from right after what was just compiled, which
+    /// is also where an oversized loop gets reported
+    fn emit_loop(&mut self, loop_start: usize, span: &Range<usize>) -> Result<(), LoxErrorS> {
+        self.emit_byte((opcode::LOOP, span.clone()))?;
+
+        // account for the two operand bytes about to be emitted
+        let offset = self.chunk().code.len() - loop_start + 2;
+        let offset: u16 = offset.try_into().map_err(|_| {
+            (
+                OverflowError::LoopTooLarge(u16::MAX as usize).into(),
+                self.prev_span(),
+            )
+        })?;
+
+        let [hi, lo] = offset.to_be_bytes();
+        self.emit_byte((hi, span.clone()))?;
+        self.emit_byte((lo, span.clone()))
+    }
+
+    /// Functions without an explicit `return` implicitly return `nil`,
+    /// while initializers always return the instance they initialized
     fn emit_return(&mut self) -> Result<(), LoxErrorS> {
-        self.emit_byte((opcode::RETURN, NO_SPAN))
+        let span = self.prev_span();
+        match self.state().kind {
+            FunctionKind::Initializer => {
+                self.emit_byte((opcode::GET_LOCAL, span.clone()))?;
+                self.emit_byte((0, span.clone()))?;
+            }
+            _ => self.emit_constant(Value::NIL, &span)?,
+        }
+        self.emit_byte((opcode::RETURN, span))
+    }
+
+    /// Emits `opcode` with a constant index, or a local or upvalue slot, as its operand
+    fn emit_indexed(
+        &mut self,
+        opcode: u8,
+        idx: usize,
+        span: &Range<usize>,
+    ) -> Result<(), LoxErrorS> {
+        self.chunk()
+            .write_indexed(opcode, idx, span)
+            .map_err(|e| (e, span.clone()))
     }
 
     fn emit_constant(&mut self, value: Value, span: &Range<usize>) -> Result<(), LoxErrorS> {
         debug!("adding constant to chunk: {} at {:?}", value, span);
-        self.chunk.add_constant(opcode::CONSTANT, value, span)
+        self.chunk().add_constant(opcode::CONSTANT, value, span)
     }
 
-    fn number(&mut self) -> Result<(), LoxErrorS> {
+    fn number(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
         trace!("calling number()");
         if let Some((Token::Number(number), span)) = &self.parser.prev {
-            return self.emit_constant(Value::from(number.clone()), &span.clone());
+            return self.emit_constant(Value::from(*number), &span.clone());
         }
         Err((InternalError::UnexpectedCodePath.into(), NO_SPAN))
     }
-    fn literal(&mut self) -> Result<(), LoxErrorS> {
+    fn variable(&mut self, can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling variable()");
+        let (name, span) = match &self.parser.prev {
+            Some((Token::Literal(name), span)) => (name.clone(), span.clone()),
+            _ => return Err((InternalError::UnexpectedCodePath.into(), self.prev_span())),
+        };
+        self.named_variable(&name, span, can_assign)
+    }
+
+    fn named_variable(
+        &mut self,
+        name: &str,
+        span: Range<usize>,
+        can_assign: bool,
+    ) -> Result<(), LoxErrorS> {
+        let state = self.states.len() - 1;
+        let (arg, get_op, set_op) = if let Some(slot) = self.resolve_local(state, name, &span)? {
+            (slot.into(), opcode::GET_LOCAL, opcode::SET_LOCAL)
+        } else if let Some(index) = self.resolve_upvalue(state, name, &span)? {
+            (index.into(), opcode::GET_UPVALUE, opcode::SET_UPVALUE)
+        } else {
+            (
+                self.identifier_constant(name, &span)?,
+                opcode::GET_GLOBAL,
+                opcode::SET_GLOBAL,
+            )
+        };
+
+        if can_assign && self.parser.matches(Token::Equal) {
+            self.expression()?;
+            self.emit_indexed(set_op, arg, &span)
+        } else {
+            self.emit_indexed(get_op, arg, &span)
+        }
+    }
+
+    fn call(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling call()");
+        let span = self.prev_span();
+        let arg_count = self.argument_list()?;
+        self.emit_byte((opcode::CALL, span.clone()))?;
+        self.emit_byte((arg_count, span))
+    }
+
+    fn argument_list(&mut self) -> Result<u8, LoxErrorS> {
+        let mut arg_count: usize = 0;
+        if !self.parser.check(&Token::RightParen) {
+            loop {
+                self.expression()?;
+                if arg_count >= MAX_ARGS {
+                    return Err((
+                        OverflowError::ExceedsArgumentCount(MAX_ARGS).into(),
+                        self.prev_span(),
+                    ));
+                }
+                arg_count += 1;
+
+                if !self.parser.matches(Token::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.parser
+            .consume(Token::RightParen, "Expected `)` after arguments")?;
+        // `MAX_ARGS` guarantees the count fits in a byte
+        Ok(arg_count as u8)
+    }
+
+    fn dot(&mut self, can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling dot()");
+        let (name, span) = self
+            .parser
+            .consume_identifier("Expected property name after `.`")?;
+        let name_constant = self.identifier_constant(&name, &span)?;
+
+        if can_assign && self.parser.matches(Token::Equal) {
+            self.expression()?;
+            self.emit_indexed(opcode::SET_PROPERTY, name_constant, &span)
+        } else if self.parser.matches(Token::LeftParen) {
+            // calling a method straight away skips allocating a bound method
+            let arg_count = self.argument_list()?;
+            self.emit_indexed(opcode::INVOKE, name_constant, &span)?;
+            self.emit_byte((arg_count, span))
+        } else {
+            self.emit_indexed(opcode::GET_PROPERTY, name_constant, &span)
+        }
+    }
+
+    fn this(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling this()");
+        let span = self.prev_span();
+        if self.classes.is_empty() {
+            return Err((CompilerError::ThisOutsideClass.into(), span));
+        }
+        // `this` is a local in slot zero, and can never be assigned to
+        self.named_variable("this", span, false)
+    }
+
+    fn super_(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling super_()");
+        let span = self.prev_span();
+        match self.classes.last() {
+            None => return Err((CompilerError::SuperOutsideClass.into(), span)),
+            Some(ClassState {
+                has_superclass: false,
+            }) => return Err((CompilerError::SuperWithoutSuperclass.into(), span)),
+            Some(_) => {}
+        }
+
+        self.parser
+            .consume(Token::Dot, "Expected `.` after `super`")?;
+        let (name, name_span) = self
+            .parser
+            .consume_identifier("Expected superclass method name")?;
+        let name_constant = self.identifier_constant(&name, &name_span)?;
+
+        self.named_variable("this", span.clone(), false)?;
+        if self.parser.matches(Token::LeftParen) {
+            let arg_count = self.argument_list()?;
+            self.named_variable("super", span.clone(), false)?;
+            self.emit_indexed(opcode::SUPER_INVOKE, name_constant, &name_span)?;
+            self.emit_byte((arg_count, name_span))
+        } else {
+            self.named_variable("super", span, false)?;
+            self.emit_indexed(opcode::GET_SUPER, name_constant, &name_span)
+        }
+    }
+
+    fn and(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling and()");
+        let span = self.prev_span();
+        let end_jump = self.emit_jump((opcode::JUMP_IF_FALSE, span.clone()))?;
+
+        self.emit_byte((opcode::POP, span.clone()))?;
+        self.parse_precedence(precedence::PREC_AND)?;
+
+        self.patch_jump(end_jump, &span)
+    }
+
+    fn or(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling or()");
+        let span = self.prev_span();
+        let else_jump = self.emit_jump((opcode::JUMP_IF_FALSE, span.clone()))?;
+        let end_jump = self.emit_jump((opcode::JUMP, span.clone()))?;
+
+        self.patch_jump(else_jump, &span)?;
+        self.emit_byte((opcode::POP, span.clone()))?;
+
+        self.parse_precedence(precedence::PREC_OR)?;
+        self.patch_jump(end_jump, &span)
+    }
+
+    fn string(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
+        trace!("calling string()");
+        if let Some((Token::String(str), span)) = self.parser.prev.clone() {
+            let value = self.intern(&str);
+            return self.emit_constant(value, &span);
+        }
+        Err((InternalError::UnexpectedCodePath.into(), NO_SPAN))
+    }
+
+    fn literal(&mut self, _can_assign: bool) -> Result<(), LoxErrorS> {
         trace!("calling literal()");
         match &self.parser.prev {
             Some((Token::True, span)) => self.emit_constant(Value::TRUE, &span.clone()),
@@ -244,141 +1242,168 @@ impl Compiler {
     }
 }
 
-fn get_rule(token: &TokenS) -> Result<&'static ParseLogic, LoxErrorS> {
+fn get_rule<'h>(token: &TokenS) -> Result<ParseLogic<'h>, LoxErrorS> {
     match &token.0 {
-        Token::Colon => Ok(&ParseLogic {
+        Token::Colon => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_TERNARY,
         }),
-        Token::QuestionMark => Ok(&ParseLogic {
+        Token::QuestionMark => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::ternary),
             precedence: precedence::PREC_TERNARY,
         }),
-        Token::LeftParen => Ok(&ParseLogic {
+        Token::LeftParen => Ok(ParseLogic {
             prefix: Some(Compiler::grouping),
-            infix: None,
-            precedence: precedence::PREC_NONE,
+            infix: Some(Compiler::call),
+            precedence: precedence::PREC_CALL,
         }),
-        Token::RightParen => Ok(&ParseLogic {
+        Token::RightParen => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::LeftBrace => Ok(&ParseLogic {
+        Token::LeftBrace => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::RightBrace => Ok(&ParseLogic {
+        Token::RightBrace => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Semicolon => Ok(&ParseLogic {
+        Token::Semicolon => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Comma => Ok(&ParseLogic {
+        Token::Comma => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Dot => Ok(&ParseLogic {
+        Token::Dot => Ok(ParseLogic {
             prefix: None,
-            infix: None,
-            precedence: precedence::PREC_NONE,
+            infix: Some(Compiler::dot),
+            precedence: precedence::PREC_CALL,
         }),
-        Token::Minus => Ok(&ParseLogic {
+        Token::Minus => Ok(ParseLogic {
             prefix: Some(Compiler::unary),
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_TERM,
         }),
-        Token::Bang => Ok(&ParseLogic {
+        Token::Bang => Ok(ParseLogic {
             prefix: Some(Compiler::unary),
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Plus => Ok(&ParseLogic {
+        Token::Plus => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_TERM,
         }),
-        Token::Slash => Ok(&ParseLogic {
+        Token::Slash => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_FACTOR,
         }),
-        Token::Star => Ok(&ParseLogic {
+        Token::Star => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_FACTOR,
         }),
-        Token::Equal => Ok(&ParseLogic {
+        Token::Equal => Ok(ParseLogic {
             prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Less => Ok(&ParseLogic {
+        Token::Less => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_COMPARISON,
         }),
-        Token::More => Ok(&ParseLogic {
+        Token::More => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_COMPARISON,
         }),
-        Token::BangEqual => Ok(&ParseLogic {
+        Token::BangEqual => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_EQUALITY,
         }),
-        Token::EqualEqual => Ok(&ParseLogic {
+        Token::EqualEqual => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_EQUALITY,
         }),
 
-        Token::LessEqual => Ok(&ParseLogic {
+        Token::LessEqual => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_COMPARISON,
         }),
-        Token::GreaterEqual => Ok(&ParseLogic {
+        Token::GreaterEqual => Ok(ParseLogic {
             prefix: None,
             infix: Some(Compiler::binary),
             precedence: precedence::PREC_COMPARISON,
         }),
-        Token::And => Ok(&ParseLogic {
+        Token::And => Ok(ParseLogic {
             prefix: None,
+            infix: Some(Compiler::and),
+            precedence: precedence::PREC_AND,
+        }),
+        Token::Or => Ok(ParseLogic {
+            prefix: None,
+            infix: Some(Compiler::or),
+            precedence: precedence::PREC_OR,
+        }),
+        Token::Literal(_) => Ok(ParseLogic {
+            prefix: Some(Compiler::variable),
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Or => Ok(&ParseLogic {
-            prefix: None,
+        Token::String(_) => Ok(ParseLogic {
+            prefix: Some(Compiler::string),
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Literal(_) => Ok(&ParseLogic {
-            prefix: None,
+        Token::Number(_) => Ok(ParseLogic {
+            prefix: Some(Compiler::number),
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::String(_) => Ok(&ParseLogic {
-            prefix: None,
+        Token::True | Token::False | Token::Nil => Ok(ParseLogic {
+            prefix: Some(Compiler::literal),
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::Number(_) => Ok(&ParseLogic {
-            prefix: Some(Compiler::number),
+        Token::Fun => Ok(ParseLogic {
+            prefix: Some(Compiler::lambda),
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
-        Token::True | Token::False | Token::Nil => Ok(&ParseLogic {
-            prefix: Some(Compiler::literal),
+        Token::This => Ok(ParseLogic {
+            prefix: Some(Compiler::this),
+            infix: None,
+            precedence: precedence::PREC_NONE,
+        }),
+        Token::Super => Ok(ParseLogic {
+            prefix: Some(Compiler::super_),
+            infix: None,
+            precedence: precedence::PREC_NONE,
+        }),
+        Token::Class
+        | Token::Else
+        | Token::For
+        | Token::If
+        | Token::Print
+        | Token::Return
+        | Token::Var
+        | Token::While => Ok(ParseLogic {
+            prefix: None,
             infix: None,
             precedence: precedence::PREC_NONE,
         }),
@@ -390,9 +1415,52 @@ fn get_rule(token: &TokenS) -> Result<&'static ParseLogic, LoxErrorS> {
     }
 }
 
-#[derive(PartialEq, PartialOrd, Clone)]
-struct ParseLogic {
-    prefix: Option<fn(&mut Compiler) -> Result<(), LoxErrorS>>,
-    infix: Option<fn(&mut Compiler) -> Result<(), LoxErrorS>>,
+type ParseFn<'h> = fn(&mut Compiler<'h>, bool) -> Result<(), LoxErrorS>;
+
+#[derive(Clone, Copy)]
+struct ParseLogic<'h> {
+    prefix: Option<ParseFn<'h>>,
+    infix: Option<ParseFn<'h>>,
     precedence: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities::table::Table, error::LoxError};
+
+    /// `depth` functions in all, counting the script
+    fn nested_functions(depth: usize) -> String {
+        "fun f() {".repeat(depth - 1) + &"}".repeat(depth - 1)
+    }
+
+    #[test]
+    fn echoes_only_the_expression_statements_of_the_line_itself() {
+        let mut heap = Heap::new();
+        let globals = Table::default();
+        let source = "var i = 0; i; while (i < 3) i = i + 1; if (true) i; { i; }";
+        let script = compile(source, 0, &mut heap, &globals, 0, Mode::Repl).unwrap();
+        let script = script.as_obj().and_then(|obj| obj.as_function()).unwrap();
+
+        let listing = script.chunk.to_string();
+        assert_eq!(listing.matches("OP_PRINT").count(), 1, "{listing}");
+    }
+
+    #[test]
+    fn rejects_functions_nested_too_deep() {
+        let mut heap = Heap::new();
+        let globals = Table::default();
+        let source = nested_functions(MAX_FUNCTION_DEPTH);
+        assert!(compile(&source, 0, &mut heap, &globals, 1, Mode::File).is_ok());
+
+        let source = nested_functions(MAX_FUNCTION_DEPTH + 1);
+        let errs = compile(&source, 0, &mut heap, &globals, 1, Mode::File).unwrap_err();
+        assert!(matches!(
+            errs.first(),
+            Some((
+                LoxError::OverflowError(OverflowError::ExceedsFunctionDepth(MAX_FUNCTION_DEPTH)),
+                _
+            ))
+        ));
+    }
+}
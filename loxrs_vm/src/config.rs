@@ -1,2 +1,61 @@
-pub const MAX_CONST_POOL: usize = 256;
-pub const MAX_STACK: usize = 256;
+/// constants addressable by `CONSTANT_LONG`'s 3 byte operand
+pub const MAX_CONST_POOL: usize = 1 << 24;
+/// constants addressable by a one byte operand
+pub const MAX_SHORT_CONST_POOL: usize = 256;
+pub const MAX_LOCALS: usize = 256;
+pub const MAX_UPVALUES: usize = 256;
+pub const MAX_ARGS: usize = 255;
+/// functions nested inside one another, counting the script itself
+pub const MAX_FUNCTION_DEPTH: usize = 256;
+/// bytes allocated before the first collection
+pub const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+/// the next collection happens once the heap grows by this factor
+pub const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// Runtime limits for the VM, which default to the values below and
+/// can be overridden through the environment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// most values the stack may hold before reporting a stack overflow
+    pub stack_max: usize,
+    /// most nested calls before reporting a stack overflow
+    pub frames_max: usize,
+    /// `0` compiles every expression as written, `1` folds constants and
+    /// fuses instructions with the peephole optimizer
+    pub opt_level: u8,
+}
+
+pub const DEFAULT_FRAMES_MAX: usize = 1024;
+/// every frame can address up to `MAX_LOCALS` slots
+pub const DEFAULT_STACK_MAX: usize = DEFAULT_FRAMES_MAX * MAX_LOCALS;
+pub const DEFAULT_OPT_LEVEL: u8 = 1;
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            stack_max: DEFAULT_STACK_MAX,
+            frames_max: DEFAULT_FRAMES_MAX,
+            opt_level: DEFAULT_OPT_LEVEL,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `LOXRS_STACK_MAX`, `LOXRS_FRAMES_MAX` and `LOXRS_OPT_LEVEL`, keeping
+    /// the default for any that are unset or not a valid number
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        fn read<T: std::str::FromStr>(key: &str, default: T) -> T {
+            std::env::var(key)
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            stack_max: read("LOXRS_STACK_MAX", default.stack_max),
+            frames_max: read("LOXRS_FRAMES_MAX", default.frames_max),
+            opt_level: read("LOXRS_OPT_LEVEL", default.opt_level),
+        }
+    }
+}
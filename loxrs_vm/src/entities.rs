@@ -1,4 +1,6 @@
 pub mod chunk;
+pub mod object;
 pub mod opcode;
 pub mod precedence;
+pub mod table;
 pub mod value;
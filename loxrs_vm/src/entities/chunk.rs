@@ -1,48 +1,114 @@
 use std::{
+    collections::HashMap,
     fmt::{Display, Formatter},
     ops::Range,
 };
 
-use arrayvec::ArrayVec;
-
 use crate::{
-    config::MAX_CONST_POOL,
+    config::{MAX_CONST_POOL, MAX_SHORT_CONST_POOL},
     error::{LoxError, LoxErrorS, OverflowError, Result as LoxResult},
 };
 
 use super::{opcode, value::Value};
 type Span = Range<usize>;
 
+/// A span shared by every byte of `code` from `start` up to the next run
+#[derive(Debug)]
+struct SpanRun {
+    start: usize,
+    span: Span,
+}
+
 #[derive(Debug, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
-    constants: ArrayVec<Value, MAX_CONST_POOL>,
-    pub spans: Vec<Span>,
+    constants: Vec<Value>,
+    /// where each constant lives in `constants`, so equal numbers and
+    /// interned strings share a single slot
+    constant_indices: HashMap<Value, usize>,
+    /// run-length encoded, since a single token usually emits several bytes
+    spans: Vec<SpanRun>,
 }
 
 impl Chunk {
     pub fn new() -> Self {
         Chunk {
             code: vec![],
-            constants: ArrayVec::new(),
+            constants: vec![],
+            constant_indices: HashMap::new(),
             spans: vec![],
         }
     }
 
+    /// Rebuilds a chunk from its serialized parts, `spans` being
+    /// `(offset, span)` pairs as returned by [Chunk::span_runs]
+    pub fn from_parts(
+        code: Vec<u8>,
+        constants: Vec<Value>,
+        spans: impl IntoIterator<Item = (usize, Span)>,
+    ) -> Self {
+        let constant_indices = constants
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| (*val, idx))
+            .collect();
+        Chunk {
+            code,
+            constants,
+            constant_indices,
+            spans: spans
+                .into_iter()
+                .map(|(start, span)| SpanRun { start, span })
+                .collect(),
+        }
+    }
+
+    /// Every run of the span table, as the offset where it starts and its span
+    pub fn span_runs(&self) -> impl Iterator<Item = (usize, &Span)> {
+        self.spans.iter().map(|run| (run.start, &run.span))
+    }
+
     pub fn write_chunk(&mut self, opcode: u8, span: Span) {
+        if self.spans.last().is_none_or(|run| run.span != span) {
+            self.spans.push(SpanRun {
+                start: self.code.len(),
+                span,
+            });
+        }
         self.code.push(opcode);
-        self.spans.push(span)
     }
 
-    fn write_constant(&mut self, val: Value) -> LoxResult<u8> {
-        self.constants.try_push(val).map_err(|_| {
-            <OverflowError as Into<LoxError>>::into(OverflowError::ExceedsConstSize(MAX_CONST_POOL))
-        })?;
-        (self.constants.len() - 1).try_into().map_err(|_| {
-            <OverflowError as Into<LoxError>>::into(OverflowError::IndexOverflow(MAX_CONST_POOL))
-        })
+    /// The source span of the byte at `offset`
+    pub fn span_at(&self, offset: usize) -> Span {
+        let run = self.spans.partition_point(|run| run.start <= offset);
+        run.checked_sub(1)
+            .map(|run| self.spans[run].span.clone())
+            .unwrap_or_default()
     }
 
+    /// Approximate bytes owned by the chunk, used to decide when to collect
+    pub fn size(&self) -> usize {
+        self.code.len()
+            + std::mem::size_of_val(self.constants.as_slice())
+            + std::mem::size_of_val(self.spans.as_slice())
+    }
+
+    fn write_constant(&mut self, val: Value) -> LoxResult<usize> {
+        if let Some(idx) = self.constant_indices.get(&val) {
+            return Ok(*idx);
+        }
+
+        if self.constants.len() >= MAX_CONST_POOL {
+            return Err(<OverflowError as Into<LoxError>>::into(
+                OverflowError::ExceedsConstSize(MAX_CONST_POOL),
+            ));
+        }
+        self.constants.push(val);
+        self.constant_indices.insert(val, self.constants.len() - 1);
+        Ok(self.constants.len() - 1)
+    }
+
+    /// Adds `val` to the pool and writes `opcode` indexing it
     pub fn add_constant(
         &mut self,
         opcode: u8,
@@ -50,20 +116,84 @@ impl Chunk {
         span: &Span,
     ) -> LoxResult<(), LoxErrorS> {
         let idx = self.write_constant(val).map_err(|e| (e, span.clone()))?;
-        self.write_chunk(opcode, span.clone());
-        self.write_chunk(idx, span.clone());
-        Ok(())
+        self.write_indexed(opcode, idx, span)
+            .map_err(|e| (e, span.clone()))
     }
 
-    pub fn read_const(&self, idx: usize) -> LoxResult<Value> {
-        if idx < MAX_CONST_POOL {
-            return Ok(self.constants[self.code[idx] as usize]);
+    /// Writes `opcode` followed by the constant index `idx`, switching to its
+    /// long form and a 3 byte operand once the pool outgrows a single byte
+    pub fn write_indexed(&mut self, opcode: u8, idx: usize, span: &Span) -> LoxResult<()> {
+        match opcode::long_form(opcode) {
+            Some(long) if idx >= MAX_SHORT_CONST_POOL => {
+                let [_, hi, mid, lo] = (idx as u32).to_be_bytes();
+                self.write_chunk(long, span.clone());
+                for byte in [hi, mid, lo] {
+                    self.write_chunk(byte, span.clone());
+                }
+            }
+            _ => {
+                let idx = short_index(idx)?;
+                self.write_chunk(opcode, span.clone());
+                self.write_chunk(idx, span.clone());
+            }
         }
+        Ok(())
+    }
+
+    /// Identifier names are interned strings, so every reference to the
+    /// same global shares a single constant slot
+    pub fn add_identifier(&mut self, name: Value) -> LoxResult<usize> {
+        self.write_constant(name)
+    }
+
+    /// Adds `val` for an operand only a single byte wide
+    pub fn add_short_constant(&mut self, val: Value) -> LoxResult<u8> {
+        let idx = self.write_constant(val)?;
+        short_index(idx)
+    }
 
-        return Err(<OverflowError as Into<LoxError>>::into(
-            OverflowError::IndexOverflow(MAX_CONST_POOL),
-        ));
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
     }
+
+    /// Back-fills the two placeholder bytes at `offset` with the distance
+    /// from the end of the jump instruction to the current end of the chunk
+    pub fn patch_jump(&mut self, offset: usize) -> LoxResult<()> {
+        let jump = self.code.len() - offset - 2;
+        let jump: u16 = jump.try_into().map_err(|_| {
+            <OverflowError as Into<LoxError>>::into(OverflowError::JumpTooLarge(u16::MAX as usize))
+        })?;
+
+        let [hi, lo] = jump.to_be_bytes();
+        self.code[offset] = hi;
+        self.code[offset + 1] = lo;
+        Ok(())
+    }
+
+    pub fn read_short(&self, idx: usize) -> u16 {
+        u16::from_be_bytes([self.code[idx], self.code[idx + 1]])
+    }
+
+    pub fn read_long(&self, idx: usize) -> usize {
+        u32::from_be_bytes([0, self.code[idx], self.code[idx + 1], self.code[idx + 2]]) as usize
+    }
+
+    /// The constant index of the instruction at `offset`, whichever form it's in
+    pub fn read_index(&self, offset: usize) -> usize {
+        match opcode::index_width(self.code[offset]) {
+            1 => self.code[offset + 1] as usize,
+            _ => self.read_long(offset + 1),
+        }
+    }
+}
+
+/// Operands of opcodes without a long form only have room for a single byte
+fn short_index(idx: usize) -> LoxResult<u8> {
+    idx.try_into().map_err(|_| {
+        <OverflowError as Into<LoxError>>::into(OverflowError::ExceedsConstSize(
+            MAX_SHORT_CONST_POOL,
+        ))
+    })
 }
 
 impl Display for Chunk {
@@ -85,6 +215,7 @@ impl Chunk {
         match self.code[idx] {
             opcode::RETURN => self.display_op_simple("OP_RETURN", idx, f),
             opcode::CONSTANT => self.display_op_one_operand("OP_CONSTANT", idx, f),
+            opcode::CONSTANT_LONG => self.display_op_constant_long("OP_CONSTANT_LONG", idx, f),
             opcode::NEGATE => self.display_op_simple("OP_NEGATE", idx, f),
             opcode::ADD => self.display_op_simple("OP_ADD", idx, f),
             opcode::SUBTRACT => self.display_op_simple("OP_SUBTRACT", idx, f),
@@ -95,12 +226,58 @@ impl Chunk {
             opcode::GREATER => self.display_op_simple("OP_GREATER", idx, f),
             opcode::EQUAL => self.display_op_simple("OP_EQUAL", idx, f),
             opcode::LESS => self.display_op_simple("OP_LESS", idx, f),
+            opcode::GREATER_EQUAL => self.display_op_simple("OP_GREATER_EQUAL", idx, f),
+            opcode::LESS_EQUAL => self.display_op_simple("OP_LESS_EQUAL", idx, f),
+            opcode::NOT_EQUAL => self.display_op_simple("OP_NOT_EQUAL", idx, f),
+            opcode::GET_LOCAL_0 => self.display_op_simple("OP_GET_LOCAL_0", idx, f),
+            opcode::GET_LOCAL_1 => self.display_op_simple("OP_GET_LOCAL_1", idx, f),
+            opcode::GET_LOCAL_2 => self.display_op_simple("OP_GET_LOCAL_2", idx, f),
+            opcode::GET_LOCAL_3 => self.display_op_simple("OP_GET_LOCAL_3", idx, f),
+            opcode::ADD_CONSTANT => self.display_op_one_operand("OP_ADD_CONSTANT", idx, f),
+            opcode::JUMP_IF_FALSE_POP => self.display_op_jump("OP_JUMP_IF_FALSE_POP", true, idx, f),
+            opcode::INCREMENT_LOCAL => self.display_op_increment("OP_INCREMENT_LOCAL", idx, f),
+            opcode::PRINT => self.display_op_simple("OP_PRINT", idx, f),
+            opcode::POP => self.display_op_simple("OP_POP", idx, f),
+            opcode::DEFINE_GLOBAL => self.display_op_identifier("OP_DEFINE_GLOBAL", idx, f),
+            opcode::GET_GLOBAL => self.display_op_identifier("OP_GET_GLOBAL", idx, f),
+            opcode::SET_GLOBAL => self.display_op_identifier("OP_SET_GLOBAL", idx, f),
+            opcode::GET_LOCAL => self.display_op_byte("OP_GET_LOCAL", idx, f),
+            opcode::SET_LOCAL => self.display_op_byte("OP_SET_LOCAL", idx, f),
+            opcode::JUMP => self.display_op_jump("OP_JUMP", true, idx, f),
+            opcode::JUMP_IF_FALSE => self.display_op_jump("OP_JUMP_IF_FALSE", true, idx, f),
+            opcode::LOOP => self.display_op_jump("OP_LOOP", false, idx, f),
+            opcode::CALL => self.display_op_byte("OP_CALL", idx, f),
+            opcode::CLOSURE => self.display_op_closure(idx, f),
+            opcode::GET_UPVALUE => self.display_op_byte("OP_GET_UPVALUE", idx, f),
+            opcode::SET_UPVALUE => self.display_op_byte("OP_SET_UPVALUE", idx, f),
+            opcode::CLOSE_UPVALUE => self.display_op_simple("OP_CLOSE_UPVALUE", idx, f),
+            opcode::CLASS => self.display_op_identifier("OP_CLASS", idx, f),
+            opcode::GET_PROPERTY => self.display_op_identifier("OP_GET_PROPERTY", idx, f),
+            opcode::SET_PROPERTY => self.display_op_identifier("OP_SET_PROPERTY", idx, f),
+            opcode::METHOD => self.display_op_identifier("OP_METHOD", idx, f),
+            opcode::INVOKE => self.display_op_invoke("OP_INVOKE", idx, f),
+            opcode::INHERIT => self.display_op_simple("OP_INHERIT", idx, f),
+            opcode::GET_SUPER => self.display_op_identifier("OP_GET_SUPER", idx, f),
+            opcode::SUPER_INVOKE => self.display_op_invoke("OP_SUPER_INVOKE", idx, f),
+            opcode::DEFINE_GLOBAL_LONG => {
+                self.display_op_identifier("OP_DEFINE_GLOBAL_LONG", idx, f)
+            }
+            opcode::GET_GLOBAL_LONG => self.display_op_identifier("OP_GET_GLOBAL_LONG", idx, f),
+            opcode::SET_GLOBAL_LONG => self.display_op_identifier("OP_SET_GLOBAL_LONG", idx, f),
+            opcode::CLOSURE_LONG => self.display_op_closure(idx, f),
+            opcode::CLASS_LONG => self.display_op_identifier("OP_CLASS_LONG", idx, f),
+            opcode::GET_PROPERTY_LONG => self.display_op_identifier("OP_GET_PROPERTY_LONG", idx, f),
+            opcode::SET_PROPERTY_LONG => self.display_op_identifier("OP_SET_PROPERTY_LONG", idx, f),
+            opcode::METHOD_LONG => self.display_op_identifier("OP_METHOD_LONG", idx, f),
+            opcode::INVOKE_LONG => self.display_op_invoke("OP_INVOKE_LONG", idx, f),
+            opcode::GET_SUPER_LONG => self.display_op_identifier("OP_GET_SUPER_LONG", idx, f),
+            opcode::SUPER_INVOKE_LONG => self.display_op_invoke("OP_SUPER_INVOKE_LONG", idx, f),
             _byte => self.display_op_simple("OP_UNKNOWN", idx, f),
         }
     }
 
     fn display_span(&self, idx: usize, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let Range { start, end } = self.spans[idx];
+        let Range { start, end } = self.span_at(idx);
         write!(f, " {start:04}-{end:04}")?;
         Ok(())
     }
@@ -118,4 +295,80 @@ impl Chunk {
         writeln!(f, "{idx:4}: {name:16} -> {}", byte).expect("Failed to write");
         idx + 2
     }
+
+    fn display_op_constant_long(&self, name: &str, idx: usize, f: &mut Formatter<'_>) -> usize {
+        let constant = &self.constants[self.read_long(idx + 1)];
+        writeln!(f, "{idx:4}: {name:16} -> {constant}").expect("Failed to write");
+        idx + 4
+    }
+
+    fn display_op_identifier(&self, name: &str, idx: usize, f: &mut Formatter<'_>) -> usize {
+        let ident = &self.constants[self.read_index(idx)];
+        writeln!(f, "{idx:4}: {name:16} -> {ident}").expect("Failed to write");
+        idx + 1 + opcode::index_width(self.code[idx])
+    }
+
+    fn display_op_invoke(&self, name: &str, idx: usize, f: &mut Formatter<'_>) -> usize {
+        let method = &self.constants[self.read_index(idx)];
+        let next = idx + 1 + opcode::index_width(self.code[idx]);
+        let arg_count = self.code[next];
+        writeln!(f, "{idx:4}: {name:16} ({arg_count} args) -> {method}").expect("Failed to write");
+        next + 1
+    }
+
+    fn display_op_byte(&self, name: &str, idx: usize, f: &mut Formatter<'_>) -> usize {
+        let slot = self.code[idx + 1];
+        writeln!(f, "{idx:4}: {name:16} {slot:4}").expect("Failed to write");
+        idx + 2
+    }
+
+    /// A local slot followed by the constant added to it
+    fn display_op_increment(&self, name: &str, idx: usize, f: &mut Formatter<'_>) -> usize {
+        let slot = self.code[idx + 1];
+        let constant = &self.constants[self.code[idx + 2] as usize];
+        writeln!(f, "{idx:4}: {name:16} {slot:4} += {constant}").expect("Failed to write");
+        idx + 3
+    }
+
+    /// Followed by an `(is_local, index)` byte pair for every captured variable
+    fn display_op_closure(&self, idx: usize, f: &mut Formatter<'_>) -> usize {
+        let function = self.constants[self.read_index(idx)];
+        let name = match self.code[idx] {
+            opcode::CLOSURE_LONG => "OP_CLOSURE_LONG",
+            _ => "OP_CLOSURE",
+        };
+        writeln!(f, "{idx:4}: {name:16} -> {function}").expect("Failed to write");
+
+        let upvalue_count = function
+            .as_obj()
+            .and_then(|obj| obj.as_function())
+            .map_or(0, |function| function.upvalue_count);
+
+        let mut offset = idx + 1 + opcode::index_width(self.code[idx]);
+        for _ in 0..upvalue_count {
+            let kind = match self.code[offset] {
+                1 => "local",
+                _ => "upvalue",
+            };
+            let index = self.code[offset + 1];
+            writeln!(f, "           {offset:4}: {:16} {kind} {index}", "|")
+                .expect("Failed to write");
+            offset += 2;
+        }
+        offset
+    }
+
+    fn display_op_jump(
+        &self,
+        name: &str,
+        forward: bool,
+        idx: usize,
+        f: &mut Formatter<'_>,
+    ) -> usize {
+        let jump = self.read_short(idx + 1) as usize;
+        let next = idx + 3;
+        let target = if forward { next + jump } else { next - jump };
+        writeln!(f, "{idx:4}: {name:16} {idx:4} -> {target}").expect("Failed to write");
+        next
+    }
 }
@@ -0,0 +1,261 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{Debug, Display, Formatter},
+    rc::Rc,
+};
+
+use super::{
+    chunk::Chunk,
+    table::{StringKey, Table},
+    value::Value,
+};
+use crate::error::Result;
+
+/// Heap-allocated lox values. A [super::value::Value] only ever holds
+/// a pointer to one of these, while the [crate::heap::Heap] owns it
+#[derive(Debug)]
+pub struct Obj {
+    pub kind: ObjKind,
+    /// set while the garbage collector traces reachable objects
+    pub is_marked: Cell<bool>,
+}
+
+#[derive(Debug)]
+pub enum ObjKind {
+    String(ObjString),
+    Function(ObjFunction),
+    Native(ObjNative),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ObjString {
+    pub value: Rc<str>,
+    pub hash: u32,
+}
+
+impl ObjString {
+    pub fn new(value: Rc<str>) -> Self {
+        let hash = hash_string(&value);
+        Self { value, hash }
+    }
+}
+
+/// FNV-1a, computed once when the string is allocated
+fn hash_string(value: &str) -> u32 {
+    value.bytes().fold(2166136261u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(16777619)
+    })
+}
+
+const TABLE_ENTRY_SIZE: usize = size_of::<StringKey>() + size_of::<Value>();
+
+/// A compiled function. The top-level script is a function without a name
+#[derive(Debug, Default)]
+pub struct ObjFunction {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<Rc<str>>,
+    /// the source file the chunk's spans point into, as numbered by the caller
+    /// of the compiler, since every line typed into the REPL is its own file
+    pub file: usize,
+}
+
+impl ObjFunction {
+    pub fn new(name: Option<Rc<str>>) -> Self {
+        Self {
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+            file: 0,
+        }
+    }
+}
+
+impl Display for ObjFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {name}>"),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value>;
+
+/// A function implemented in rust, callable from lox like any other function
+pub struct ObjNative {
+    pub name: Rc<str>,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl Debug for ObjNative {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ObjNative({})", self.name)
+    }
+}
+
+/// A function paired with the variables it captured from enclosing scopes.
+/// Every function is wrapped in one of these at runtime
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: Value,
+    /// every entry is an [ObjUpvalue]
+    pub upvalues: Vec<Value>,
+}
+
+impl ObjClosure {
+    pub fn function(&self) -> &ObjFunction {
+        self.function
+            .as_obj()
+            .and_then(Obj::as_function)
+            .expect("closures are only ever built around functions")
+    }
+}
+
+/// A captured variable. It points into the VM stack while the variable
+/// is still live there, and holds the value itself once it's closed over
+#[derive(Debug)]
+pub struct ObjUpvalue {
+    pub location: Cell<UpvalueLocation>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UpvalueLocation {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: Rc<str>,
+    /// every entry is an [ObjClosure]
+    pub methods: RefCell<Table>,
+}
+
+impl ObjClass {
+    pub fn new(name: Rc<str>) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(Table::default()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    /// always an [ObjClass]
+    pub class: Value,
+    pub fields: RefCell<Table>,
+}
+
+impl ObjInstance {
+    pub fn new(class: Value) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(Table::default()),
+        }
+    }
+
+    pub fn class(&self) -> &ObjClass {
+        self.class
+            .as_obj()
+            .and_then(Obj::as_class)
+            .expect("instances are only ever built from classes")
+    }
+}
+
+/// A method accessed on an instance, which remembers the instance
+/// so that `this` is bound once it's called
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    /// always an [ObjClosure]
+    pub method: Value,
+}
+
+impl Obj {
+    pub fn new(kind: ObjKind) -> Self {
+        Self {
+            kind,
+            is_marked: Cell::new(false),
+        }
+    }
+
+    /// Approximate bytes owned by this object, used to decide when to collect
+    pub fn size(&self) -> usize {
+        let owned = match &self.kind {
+            ObjKind::String(str) => str.value.len(),
+            ObjKind::Function(function) => size_of::<ObjFunction>() + function.chunk.size(),
+            ObjKind::Closure(closure) => closure.upvalues.capacity() * size_of::<Value>(),
+            ObjKind::Class(class) => class.methods.borrow().capacity() * TABLE_ENTRY_SIZE,
+            ObjKind::Instance(instance) => instance.fields.borrow().capacity() * TABLE_ENTRY_SIZE,
+            ObjKind::Native(_) | ObjKind::Upvalue(_) | ObjKind::BoundMethod(_) => 0,
+        };
+        size_of::<Obj>() + owned
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        match &self.kind {
+            ObjKind::String(str) => Some(str),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&ObjFunction> {
+        match &self.kind {
+            ObjKind::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub fn as_closure(&self) -> Option<&ObjClosure> {
+        match &self.kind {
+            ObjKind::Closure(closure) => Some(closure),
+            _ => None,
+        }
+    }
+
+    pub fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+        match &self.kind {
+            ObjKind::Upvalue(upvalue) => Some(upvalue),
+            _ => None,
+        }
+    }
+
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        match &self.kind {
+            ObjKind::Class(class) => Some(class),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        match &self.kind {
+            ObjKind::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Obj {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ObjKind::String(str) => write!(f, "{}", str.value),
+            ObjKind::Function(function) => write!(f, "{function}"),
+            ObjKind::Native(_) => write!(f, "<native fn>"),
+            ObjKind::Closure(closure) => write!(f, "{}", closure.function()),
+            ObjKind::Upvalue(_) => write!(f, "upvalue"),
+            ObjKind::Class(class) => write!(f, "{}", class.name),
+            ObjKind::Instance(instance) => write!(f, "{} instance", instance.class().name),
+            ObjKind::BoundMethod(bound) => write!(f, "{}", bound.method),
+        }
+    }
+}
@@ -12,5 +12,121 @@ iota! {
     NOT,
     GREATER,
     EQUAL,
-    LESS
+    LESS,
+    PRINT,
+    POP,
+    DEFINE_GLOBAL,
+    GET_GLOBAL,
+    SET_GLOBAL,
+    GET_LOCAL,
+    SET_LOCAL,
+    JUMP,
+    JUMP_IF_FALSE,
+    LOOP,
+    CALL,
+    CLOSURE,
+    GET_UPVALUE,
+    SET_UPVALUE,
+    CLOSE_UPVALUE,
+    CLASS,
+    GET_PROPERTY,
+    SET_PROPERTY,
+    METHOD,
+    INVOKE,
+    INHERIT,
+    GET_SUPER,
+    SUPER_INVOKE,
+    CONSTANT_LONG,
+    // fused forms of `LESS NOT`, `GREATER NOT` and `EQUAL NOT`, so `NaN` still
+    // compares the same way whichever form the compiler emits
+    GREATER_EQUAL,
+    LESS_EQUAL,
+    NOT_EQUAL,
+    // specialized forms the optimizer emits for hot paths
+    GET_LOCAL_0,
+    GET_LOCAL_1,
+    GET_LOCAL_2,
+    GET_LOCAL_3,
+    ADD_CONSTANT,
+    JUMP_IF_FALSE_POP,
+    INCREMENT_LOCAL,
+    // forms of the opcodes naming a constant that take a 3 byte index, like
+    // `CONSTANT_LONG`, once the pool outgrows a single byte
+    DEFINE_GLOBAL_LONG,
+    GET_GLOBAL_LONG,
+    SET_GLOBAL_LONG,
+    CLOSURE_LONG,
+    CLASS_LONG,
+    GET_PROPERTY_LONG,
+    SET_PROPERTY_LONG,
+    METHOD_LONG,
+    INVOKE_LONG,
+    GET_SUPER_LONG,
+    SUPER_INVOKE_LONG
+}
+
+/// Every opcode whose first operand indexes the constant pool, paired with
+/// its long form
+const LONG_FORMS: [(u8, u8); 12] = [
+    (CONSTANT, CONSTANT_LONG),
+    (DEFINE_GLOBAL, DEFINE_GLOBAL_LONG),
+    (GET_GLOBAL, GET_GLOBAL_LONG),
+    (SET_GLOBAL, SET_GLOBAL_LONG),
+    (CLOSURE, CLOSURE_LONG),
+    (CLASS, CLASS_LONG),
+    (GET_PROPERTY, GET_PROPERTY_LONG),
+    (SET_PROPERTY, SET_PROPERTY_LONG),
+    (METHOD, METHOD_LONG),
+    (INVOKE, INVOKE_LONG),
+    (GET_SUPER, GET_SUPER_LONG),
+    (SUPER_INVOKE, SUPER_INVOKE_LONG),
+];
+
+/// The form of `op` taking a 3 byte constant index, if it has one
+pub fn long_form(op: u8) -> Option<u8> {
+    LONG_FORMS
+        .iter()
+        .find(|(short, _)| *short == op)
+        .map(|(_, long)| *long)
+}
+
+/// `op` with a single byte constant index, or `op` itself if it isn't a long form
+pub fn short_form(op: u8) -> u8 {
+    LONG_FORMS
+        .iter()
+        .find(|(_, long)| *long == op)
+        .map_or(op, |(short, _)| *short)
+}
+
+/// How many bytes the constant index following `op` takes up
+pub fn index_width(op: u8) -> usize {
+    if short_form(op) == op {
+        1
+    } else {
+        3
+    }
+}
+
+/// How many operand bytes follow `op`, or [None] if it isn't an opcode.
+/// `CLOSURE` and `CLOSURE_LONG` are additionally followed by two bytes per captured upvalue
+pub fn operand_width(op: u8) -> Option<usize> {
+    let width = match op {
+        RETURN | NEGATE | ADD | SUBTRACT | MULTIPLY | DIVIDE | TERNARY_LOGICAL | NOT | GREATER
+        | EQUAL | LESS | PRINT | POP | CLOSE_UPVALUE | INHERIT | GREATER_EQUAL | LESS_EQUAL
+        | NOT_EQUAL | GET_LOCAL_0 | GET_LOCAL_1 | GET_LOCAL_2 | GET_LOCAL_3 => 0,
+        CONSTANT | DEFINE_GLOBAL | GET_GLOBAL | SET_GLOBAL | GET_LOCAL | SET_LOCAL | CALL
+        | CLOSURE | GET_UPVALUE | SET_UPVALUE | CLASS | GET_PROPERTY | SET_PROPERTY | METHOD
+        | GET_SUPER | ADD_CONSTANT => 1,
+        JUMP | JUMP_IF_FALSE | LOOP | INVOKE | SUPER_INVOKE | JUMP_IF_FALSE_POP
+        | INCREMENT_LOCAL => 2,
+        _ => {
+            let short = short_form(op);
+            if short == op {
+                return None;
+            }
+            // the index grows by two bytes, any other operands stay the same
+            operand_width(short)? + 2
+        }
+    };
+    Some(width)
 }
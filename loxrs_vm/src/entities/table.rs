@@ -0,0 +1,65 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hash, Hasher},
+};
+
+use super::{object::ObjString, value::Value};
+
+/// Hash table keyed by interned strings, used for globals and fields.
+/// Keys hash to the string's precomputed hash and compare by pointer, so
+/// lookups never touch the string contents
+pub type Table = HashMap<StringKey, Value, BuildHasherDefault<PrehashedHasher>>;
+
+/// An interned string [Value]. Interning guarantees that two keys with
+/// equal contents also share the same object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringKey(Value);
+
+impl StringKey {
+    pub fn as_string(&self) -> &ObjString {
+        self.0
+            .as_string()
+            .expect("string keys are only built from string values")
+    }
+}
+
+impl TryFrom<Value> for StringKey {
+    type Error = ();
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value.as_string() {
+            Some(_) => Ok(Self(value)),
+            None => Err(()),
+        }
+    }
+}
+
+impl From<StringKey> for Value {
+    fn from(key: StringKey) -> Self {
+        key.0
+    }
+}
+
+impl Hash for StringKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.as_string().hash);
+    }
+}
+
+/// Passes through the hash already computed by [ObjString]
+#[derive(Debug, Default)]
+pub struct PrehashedHasher(u64);
+
+impl Hasher for PrehashedHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("`PrehashedHasher` only accepts precomputed `u32` hashes");
+    }
+
+    fn write_u32(&mut self, hash: u32) {
+        self.0 = hash as u64;
+    }
+}
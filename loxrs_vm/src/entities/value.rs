@@ -1,7 +1,9 @@
 use crate::error::{ConversionError, LoxError, Result};
 use std::{fmt::Display, mem};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+use super::object::{Obj, ObjString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Value(u64);
 
 // ensure values are 8 bytes long
@@ -29,6 +31,16 @@ impl Value {
         self.0 == Self::NIL.0
     }
 
+    /// Equality as seen by `==` in lox
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self.try_number(), other.try_number()) {
+            // compared as floats so that `NaN != NaN`
+            (Ok(a), Ok(b)) => a == b,
+            // interned strings and every other value compare by identity
+            _ => self == other,
+        }
+    }
+
     pub fn is_falsey(&self) -> bool {
         self.is_false() || self.is_nil()
     }
@@ -68,6 +80,24 @@ impl Value {
         f64::from_bits(self.0)
     }
 
+    /// Dereferences the boxed object pointer. Objects live as long as the
+    /// [crate::heap::Heap] that allocated them
+    pub fn as_obj(&self) -> Option<&Obj> {
+        // SAFETY: object values are only ever built from heap allocated pointers
+        self.as_obj_ptr().and_then(|ptr| unsafe { ptr.as_ref() })
+    }
+
+    pub fn as_obj_ptr(&self) -> Option<*mut Obj> {
+        if !self.is_obj() {
+            return None;
+        }
+        Some((self.0 & !(Self::SIGN_BIT | Self::QNAN_BIT)) as *mut Obj)
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        self.as_obj().and_then(|obj| obj.as_string())
+    }
+
     pub fn try_number(&self) -> Result<f64, LoxError> {
         if self.is_number() {
             return Ok(self.as_number());
@@ -82,6 +112,12 @@ impl From<f64> for Value {
     }
 }
 
+impl From<*mut Obj> for Value {
+    fn from(value: *mut Obj) -> Self {
+        Self(Self::SIGN_BIT | Self::QNAN_BIT | value as u64)
+    }
+}
+
 impl From<bool> for Value {
     fn from(value: bool) -> Self {
         match value {
@@ -105,6 +141,10 @@ impl Display for Value {
             return write!(f, "nil");
         }
 
+        if let Some(obj) = self.as_obj() {
+            return write!(f, "{obj}");
+        }
+
         write!(f, "{}", self.0)
     }
 }
@@ -8,6 +8,7 @@ pub type LoxErrorS = Span<LoxError>;
 
 pub type Result<T, U = LoxError> = std::result::Result<T, U>;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error, Clone)]
 pub enum LoxError {
     #[error("OverflowError: {0}")]
@@ -24,6 +25,12 @@ pub enum LoxError {
     SyntaxError(SyntaxError),
     #[error("Compiler Error: {0}")]
     CompilerError(CompilerError),
+    #[error("Runtime Error: {0}")]
+    RuntimeError(RuntimeError),
+    #[error("LoadError: {0}")]
+    LoadError(LoadError),
+    #[error("VerifyError: {0}")]
+    VerifyError(VerifyError),
 }
 
 #[derive(Debug, Error, Clone, PartialEq)]
@@ -44,20 +51,15 @@ impl Default for ScannerError {
     }
 }
 
-#[derive(Debug, Error, Clone, PartialEq)]
+#[derive(Debug, Error, Clone, PartialEq, Default)]
 pub enum SyntaxError {
+    #[default]
     #[error("Invalid Syntax")]
     InvalidSyntax,
     #[error("{0}")]
     UnexpectedValue(String),
 }
 
-impl Default for SyntaxError {
-    fn default() -> Self {
-        Self::InvalidSyntax
-    }
-}
-
 /// Error type returned by calling `lex.slice().parse()` to u8.
 impl From<ParseIntError> for ScannerError {
     fn from(err: ParseIntError) -> Self {
@@ -73,8 +75,22 @@ impl From<ParseIntError> for ScannerError {
 pub enum OverflowError {
     #[error("exceeded max amount of constants ({0}) in a scope")]
     ExceedsConstSize(usize),
-    #[error("Index overflow exceeds ({0})")]
-    IndexOverflow(usize),
+    #[error("Too many local variables ({0}) in function")]
+    ExceedsLocalSize(usize),
+    #[error("Too many closure variables ({0}) in function")]
+    ExceedsUpvalueSize(usize),
+    #[error("Too much code to jump over, exceeds ({0}) bytes")]
+    JumpTooLarge(usize),
+    #[error("Loop body too large, exceeds ({0}) bytes")]
+    LoopTooLarge(usize),
+    #[error("Can't have more than ({0}) parameters")]
+    ExceedsParameterCount(usize),
+    #[error("Can't have more than ({0}) arguments")]
+    ExceedsArgumentCount(usize),
+    #[error("Can't nest functions more than ({0}) deep")]
+    ExceedsFunctionDepth(usize),
+    #[error("Stack overflow.")]
+    StackOverflow,
 }
 
 #[derive(Debug, Error, Clone)]
@@ -105,6 +121,122 @@ pub enum CompilerError {
     ParseLogicNotFound(String),
     #[error("No implementation logic for token: {0}")]
     UnimplementedType(String),
+    #[error("Can't read local variable `{0}` in its own initializer")]
+    ReadInOwnInitializer(String),
+    #[error("Already a variable named `{0}` in this scope")]
+    AlreadyDeclared(String),
+    #[error("Can't return from top-level code")]
+    ReturnFromTopLevel,
+    #[error("Can't return a value from an initializer")]
+    ReturnFromInitializer,
+    #[error("Can't use `this` outside of a class")]
+    ThisOutsideClass,
+    #[error("A class can't inherit from itself")]
+    InheritFromSelf,
+    #[error("Can't use `super` outside of a class")]
+    SuperOutsideClass,
+    #[error("Can't use `super` in a class with no superclass")]
+    SuperWithoutSuperclass,
+}
+
+#[derive(Debug, Error, Clone)]
+pub enum RuntimeError {
+    #[error("Undefined variable '{0}'.")]
+    UndefinedVariable(String),
+    #[error("Operands must be two numbers or two strings.")]
+    InvalidAddOperands,
+    #[error("Expected {0} arguments but got {1}.")]
+    ArityMismatch(usize, usize),
+    #[error("Can only call functions and classes.")]
+    NotCallable,
+    #[error("Only instances have properties.")]
+    NotAnInstance,
+    #[error("Only instances have fields.")]
+    FieldOnNonInstance,
+    #[error("Only instances have methods.")]
+    MethodOnNonInstance,
+    #[error("Undefined property '{0}'.")]
+    UndefinedProperty(String),
+    #[error("Superclass must be a class.")]
+    SuperclassNotAClass,
+    #[error("assertion failed: {0}")]
+    AssertionFailed(String),
+}
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum LoadError {
+    #[error("not a compiled lox file")]
+    InvalidMagic,
+    #[error("unsupported bytecode version {0}, expected {1}")]
+    UnsupportedVersion(u16, u16),
+    #[error("file ends unexpectedly")]
+    Truncated,
+    #[error("unknown constant tag (0x{0:x})")]
+    UnknownConstant(u8),
+    #[error("string is not valid UTF-8")]
+    InvalidString,
+    #[error("span {0:?} lies outside of the source")]
+    InvalidSpan(std::ops::Range<usize>),
+    #[error("span run at offset {0} is out of order or past the end of the code")]
+    InvalidSpanOffset(usize),
+    #[error("number constant (0x{0:x}) doesn't encode a number")]
+    InvalidNumber(u64),
+    #[error("functions are nested more than {0} deep")]
+    TooDeep(usize),
+    #[error("unexpected bytes after the script")]
+    TrailingBytes,
+}
+
+/// Every variant but `TooDeep` and `InvalidScript` starts with the offset of the offending instruction
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum VerifyError {
+    #[error("unknown opcode (0x{1:x}) at offset {0}")]
+    UnknownOpcode(usize, u8),
+    #[error("instruction at offset {0} is missing its operands")]
+    MissingOperands(usize),
+    #[error("instruction at offset {0} refers to constant {1}, which doesn't exist")]
+    InvalidConstant(usize, usize),
+    #[error("instruction at offset {0} expects an identifier constant")]
+    ExpectedIdentifier(usize),
+    #[error("instruction at offset {0} expects a function constant")]
+    ExpectedFunction(usize),
+    #[error("jump at offset {0} doesn't land on the start of an instruction")]
+    InvalidJump(usize),
+    #[error("execution runs past the end of the code at offset {0}")]
+    RunsPastEnd(usize),
+    #[error("instruction at offset {0} pops more values than are on the stack")]
+    StackUnderflow(usize),
+    #[error("stack holds {1} values at offset {0} on one path but {2} on another")]
+    InconsistentStack(usize, usize, usize),
+    #[error("instruction at offset {0} refers to local slot {1}, which doesn't exist")]
+    InvalidLocal(usize, u8),
+    #[error("instruction at offset {0} refers to upvalue {1}, which doesn't exist")]
+    InvalidUpvalue(usize, u8),
+    #[error("functions are nested more than {0} deep")]
+    TooDeep(usize),
+    #[error("the script can't take parameters or capture variables")]
+    InvalidScript,
+}
+
+impl VerifyError {
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::UnknownOpcode(offset, _)
+            | Self::MissingOperands(offset)
+            | Self::InvalidConstant(offset, _)
+            | Self::ExpectedIdentifier(offset)
+            | Self::ExpectedFunction(offset)
+            | Self::InvalidJump(offset)
+            | Self::RunsPastEnd(offset)
+            | Self::StackUnderflow(offset)
+            | Self::InconsistentStack(offset, _, _)
+            | Self::InvalidLocal(offset, _)
+            | Self::InvalidUpvalue(offset, _) => *offset,
+            // the start of the function holding the nested one
+            Self::TooDeep(_) => 0,
+            Self::InvalidScript => 0,
+        }
+    }
 }
 
 macro_rules! from_err {
@@ -124,27 +256,25 @@ from_err!(
     ConversionError,
     InvalidAccessError,
     ScannerError,
-    SyntaxError
+    SyntaxError,
+    RuntimeError,
+    LoadError,
+    VerifyError
 );
 
 #[derive(Debug, Clone)]
-pub struct Label(pub codespan_reporting::diagnostic::Label<()>);
+pub struct Label(pub codespan_reporting::diagnostic::Label<usize>);
 
-impl From<LoxErrorS> for Label {
-    fn from((err, range): LoxErrorS) -> Self {
+/// An error along with the file its span points into
+impl From<(usize, LoxErrorS)> for Label {
+    fn from((file, (err, range)): (usize, LoxErrorS)) -> Self {
         match &err {
-            LoxError::ScannerError(lexer_error) => match lexer_error {
-                ScannerError::UnrecognizedInput(unrecognized) => Label(
-                    codespan_reporting::diagnostic::Label::secondary((), range)
-                        .with_message(unrecognized),
-                ),
-                _ => Label(
-                    codespan_reporting::diagnostic::Label::primary((), range)
-                        .with_message(err.to_string()),
-                ),
-            },
+            LoxError::ScannerError(ScannerError::UnrecognizedInput(unrecognized)) => Label(
+                codespan_reporting::diagnostic::Label::secondary(file, range)
+                    .with_message(unrecognized),
+            ),
             _ => Label(
-                codespan_reporting::diagnostic::Label::primary((), range)
+                codespan_reporting::diagnostic::Label::primary(file, range)
                     .with_message(err.to_string()),
             ),
         }
@@ -0,0 +1,194 @@
+use std::{collections::HashMap, rc::Rc};
+
+use log::{debug, trace};
+
+use crate::{
+    config::{GC_HEAP_GROW_FACTOR, GC_INITIAL_THRESHOLD},
+    entities::{
+        object::{Obj, ObjKind, ObjString, UpvalueLocation},
+        table::{StringKey, Table},
+        value::Value,
+    },
+};
+
+/// Owns every object allocated by the compiler and the VM, and reclaims
+/// the unreachable ones with a mark-and-sweep collector.
+///
+/// The heap doesn't know its roots, so allocating never collects on its own.
+/// Owners check [Heap::should_collect] before allocating, mark their roots
+/// and then call [Heap::collect]
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<*mut Obj>,
+    /// every live string, so that equal contents share one object. Entries
+    /// don't keep their string alive, and are dropped when it's collected
+    strings: HashMap<Rc<str>, *mut Obj>,
+    /// looked up on every instantiation, so it's interned once and always kept alive
+    init_string: Value,
+    /// marked objects whose references haven't been traced yet
+    gray: Vec<*mut Obj>,
+    bytes_allocated: usize,
+    next_gc: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        let mut heap = Self {
+            objects: vec![],
+            strings: HashMap::new(),
+            init_string: Value::NIL,
+            gray: vec![],
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+        };
+        heap.init_string = heap.intern("init");
+        heap
+    }
+
+    pub fn init_string(&self) -> StringKey {
+        StringKey::try_from(self.init_string).expect("interning always produces a string")
+    }
+
+    pub fn alloc(&mut self, kind: ObjKind) -> Value {
+        let obj = Box::into_raw(Box::new(Obj::new(kind)));
+        // SAFETY: just allocated above
+        let size = unsafe { (*obj).size() };
+        self.bytes_allocated += size;
+        trace!("allocated {size} bytes at {:p}", obj);
+
+        self.objects.push(obj);
+        Value::from(obj)
+    }
+
+    /// Returns the existing object for `value` if it was already interned
+    pub fn intern(&mut self, value: &str) -> Value {
+        if let Some(obj) = self.strings.get(value) {
+            return Value::from(*obj);
+        }
+
+        let value: Rc<str> = Rc::from(value);
+        let interned = self.alloc(ObjKind::String(ObjString::new(Rc::clone(&value))));
+        if let Some(obj) = interned.as_obj_ptr() {
+            self.strings.insert(value, obj);
+        }
+        interned
+    }
+
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "stress_gc") || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        let Some(ptr) = value.as_obj_ptr() else {
+            return;
+        };
+        // SAFETY: object values only ever point at live objects owned by this heap
+        let obj = unsafe { &*ptr };
+        if obj.is_marked.replace(true) {
+            return;
+        }
+        self.gray.push(ptr);
+    }
+
+    pub fn mark_table(&mut self, table: &Table) {
+        for (key, value) in table {
+            self.mark_value(Value::from(*key));
+            self.mark_value(*value);
+        }
+    }
+
+    /// Traces everything reachable from the already marked roots,
+    /// then frees every object that wasn't reached
+    pub fn collect(&mut self) {
+        let before = self.bytes_allocated;
+        debug!("gc begin, {before} bytes allocated");
+
+        self.mark_value(self.init_string);
+        while let Some(ptr) = self.gray.pop() {
+            self.blacken(ptr);
+        }
+
+        // SAFETY: every interned string is a live object owned by this heap
+        self.strings
+            .retain(|_, obj| unsafe { (**obj).is_marked.get() });
+        self.sweep();
+
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(GC_INITIAL_THRESHOLD);
+        debug!(
+            "gc end, collected {} bytes ({before} -> {}), next at {}",
+            before.saturating_sub(self.bytes_allocated),
+            self.bytes_allocated,
+            self.next_gc
+        );
+    }
+
+    fn blacken(&mut self, ptr: *mut Obj) {
+        // SAFETY: only live objects are ever pushed onto the gray stack
+        let obj = unsafe { &*ptr };
+        trace!("blacken {:p}: {obj}", ptr);
+
+        match &obj.kind {
+            ObjKind::String(_) | ObjKind::Native(_) => {}
+            ObjKind::Function(function) => {
+                for constant in function.chunk.constants() {
+                    self.mark_value(*constant);
+                }
+            }
+            ObjKind::Closure(closure) => {
+                self.mark_value(closure.function);
+                for upvalue in &closure.upvalues {
+                    self.mark_value(*upvalue);
+                }
+            }
+            // open upvalues point into the stack, which is a root already
+            ObjKind::Upvalue(upvalue) => {
+                if let UpvalueLocation::Closed(value) = upvalue.location.get() {
+                    self.mark_value(value);
+                }
+            }
+            ObjKind::Class(class) => self.mark_table(&class.methods.borrow()),
+            ObjKind::Instance(instance) => {
+                self.mark_value(instance.class);
+                self.mark_table(&instance.fields.borrow());
+            }
+            ObjKind::BoundMethod(bound) => {
+                self.mark_value(bound.receiver);
+                self.mark_value(bound.method);
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut bytes_allocated = 0;
+        self.objects.retain(|&ptr| {
+            // SAFETY: every pointer was created by `Box::into_raw` in `alloc`
+            // and is only ever freed here or when the heap is dropped
+            let obj = unsafe { &*ptr };
+            if obj.is_marked.replace(false) {
+                bytes_allocated += obj.size();
+                return true;
+            }
+
+            trace!("free {:p}", ptr);
+            drop(unsafe { Box::from_raw(ptr) });
+            false
+        });
+        self.bytes_allocated = bytes_allocated;
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for obj in self.objects.drain(..) {
+            // SAFETY: every pointer was created by `Box::into_raw` in `alloc`
+            // and is only ever freed here or by `sweep`
+            drop(unsafe { Box::from_raw(obj) });
+        }
+    }
+}
@@ -7,7 +7,7 @@ use std::{
 
 use codespan_reporting::{
     diagnostic::Diagnostic,
-    files::SimpleFile,
+    files::{Files as _, SimpleFiles},
     term::{
         self,
         termcolor::{ColorChoice, StandardStream},
@@ -16,20 +16,28 @@ use codespan_reporting::{
 use log::error;
 
 use crate::{
-    error::{Label, LoxError, LoxErrorS},
-    vm::VM,
+    compiler::Mode,
+    config::Config,
+    error::{Label, LoxError, LoxErrorS, OverflowError},
+    vm::{TraceFrame, VM},
 };
 
+const BYTECODE_EXTENSION: &str = ".loxc";
+
+/// Every source the VM has compiled, which the spans of errors point into
+type Files = SimpleFiles<String, String>;
+
 pub fn read_input() {
     let args: Vec<String> = env::args().collect();
 
-    match args.len() {
-        1 => repl(),
-        2 => {
-            run_file(&args[1]);
-        }
+    match args.as_slice() {
+        [_] => repl(),
+        [_, flag, out, filename] if flag == "--compile" => compile_file(filename, out),
+        [_, filename] if filename.ends_with(BYTECODE_EXTENSION) => run_bytecode(filename),
+        [_, filename] => run_file(filename),
         _ => {
             println!("USAGE: lox-rs [name of file]");
+            println!("       lox-rs --compile [output file] [name of file]");
             exit(64); // EX_USAGE error code
         }
     }
@@ -40,7 +48,9 @@ fn repl() {
     println!("Enter statements separated by ENTER.");
     println!("EXIT with CTRL-D.");
 
-    let mut vm = VM::new();
+    // functions declared on earlier lines keep pointing into them
+    let mut files = Files::new();
+    let mut vm = VM::new(Config::from_env());
     loop {
         print!("> ");
         let _ = io::stdout().flush();
@@ -56,25 +66,27 @@ fn repl() {
             }
         };
 
-        // TODO this simply lets the VM keep on chugging along, without preserving any
-        // previous data. This plus the chunk functionality needs to be revised to support
-        // full REPL functionality
-        vm.newline();
-        let _ = vm
-            .interpret(&statement)
-            .inspect_err(|errs| report_errors(errs, "REPL input", &statement));
+        // every line runs on the same VM, so globals, functions and classes carry over
+        let file = files.add("REPL input".to_owned(), statement.clone());
+        if let Err(errs) = vm.interpret(&statement, file, Mode::Repl) {
+            report_errors(&files, file, &errs, &vm.take_trace());
+        }
     }
 }
 
 fn run_file(filename: &String) {
     println!("you provided a file: {filename}.");
 
-    let mut vm = VM::new();
+    let mut vm = VM::new(Config::from_env());
     match fs::read_to_string(filename) {
         Ok(str) => {
-            let _ = vm
-                .interpret(&str)
-                .inspect_err(|errs| report_errors(errs, filename, &str));
+            let mut files = Files::new();
+            let file = files.add(filename.to_owned(), str.clone());
+            if let Err(errs) = vm.interpret(&str, file, Mode::File) {
+                let trace = vm.take_trace();
+                report_errors(&files, file, &errs, &trace);
+                exit(error_code(&trace));
+            }
         }
         Err(e) => {
             error!("Error reading file: {e}");
@@ -83,29 +95,159 @@ fn run_file(filename: &String) {
     }
 }
 
-fn report_errors(errs: &Vec<LoxErrorS>, filename: &str, source: &str) {
+fn compile_file(filename: &String, out: &String) {
+    let source = read_file(filename);
+    let mut vm = VM::new(Config::from_env());
+    match vm.compile_to_bytes(&source) {
+        Ok(bytes) => {
+            if let Err(e) = fs::write(out, bytes) {
+                error!("Error writing file: {e}");
+                exit(73); // EX_CANTCREAT
+            }
+        }
+        Err(errs) => {
+            let mut files = Files::new();
+            let file = files.add(filename.to_owned(), source);
+            report_errors(&files, file, &errs, &[]);
+            exit(65); // EX_DATAERR
+        }
+    }
+}
+
+/// Runs a file written by `--compile`, without scanning or compiling it again
+fn run_bytecode(filename: &String) {
+    println!("you provided a file: {filename}.");
+
+    let bytes = fs::read(filename).unwrap_or_else(|e| {
+        error!("Error reading file: {e}");
+        exit(66); // EX_NOINPUT
+    });
+    let mut vm = VM::new(Config::from_env());
+    let mut files = Files::new();
+    match vm.load(&bytes) {
+        Ok((source, script)) => {
+            // loaded functions point into file `0`, which this is as the first one added
+            let file = files.add(filename.to_owned(), source);
+            if let Err(errs) = vm.execute(script) {
+                let trace = vm.take_trace();
+                report_errors(&files, file, &errs, &trace);
+                exit(error_code(&trace));
+            }
+        }
+        Err(err) => {
+            let file = files.add(filename.to_owned(), String::new());
+            report_errors(&files, file, &[err], &[]);
+            exit(65); // EX_DATAERR
+        }
+    }
+}
+
+/// Only an error raised while the script runs leaves a stack trace behind
+fn error_code(trace: &[TraceFrame]) -> i32 {
+    match trace.is_empty() {
+        true => 65,  // EX_DATAERR
+        false => 70, // EX_SOFTWARE
+    }
+}
+
+fn read_file(filename: &String) -> String {
+    fs::read_to_string(filename).unwrap_or_else(|e| {
+        error!("Error reading file: {e}");
+        exit(66); // EX_NOINPUT
+    })
+}
+
+/// Errors from compiling point into `file`, the source just compiled, while
+/// a runtime error points into the file of the function it happened in
+fn report_errors(files: &Files, file: usize, errs: &[LoxErrorS], trace: &[TraceFrame]) {
     let mut error_map: HashMap<&'static str, Vec<Label>> = HashMap::new();
     for err in errs {
-        match &err.0 {
-            LoxError::ScannerError(_) => error_map
-                .entry("Syntax Error")
-                .or_insert(vec![])
-                .push((err.0.clone(), err.1.clone()).into()),
-            _ => error_map
-                .entry("Runtime Error")
-                .or_insert(vec![])
-                .push((err.0.clone(), err.1.clone()).into()),
-        }
+        let category = match &err.0 {
+            // the only overflow that can happen while the program runs
+            LoxError::OverflowError(OverflowError::StackOverflow) => "Runtime Error",
+            LoxError::LoadError(_) | LoxError::VerifyError(_) => "Load Error",
+            LoxError::ScannerError(_) => "Syntax Error",
+            LoxError::SyntaxError(_) | LoxError::CompilerError(_) | LoxError::OverflowError(_) => {
+                "Compile Error"
+            }
+            _ => "Runtime Error",
+        };
+        // a function declared on an earlier line of the REPL runs code from that line
+        let file = match category {
+            "Runtime Error" => trace.first().map_or(file, |frame| frame.file),
+            _ => file,
+        };
+        error_map
+            .entry(category)
+            .or_insert(vec![])
+            .push((file, err.clone()).into());
     }
 
-    let file = SimpleFile::new(filename, source);
     let writer = StandardStream::stderr(ColorChoice::Always);
     let config = codespan_reporting::term::Config::default();
 
     for (str, labels) in error_map {
-        let diagnostic: Diagnostic<()> = Diagnostic::error()
+        let mut diagnostic: Diagnostic<usize> = Diagnostic::error()
             .with_message(str)
             .with_labels(labels.iter().map(|el| el.0.clone()).collect());
-        term::emit(&mut writer.lock(), &config, &file, &diagnostic).unwrap();
+        if str == "Runtime Error" && !trace.is_empty() {
+            diagnostic = diagnostic.with_notes(vec![traceback(trace, files)]);
+        }
+        term::emit(&mut writer.lock(), &config, files, &diagnostic).unwrap();
+    }
+}
+
+/// Lists where every frame was when the error happened, innermost first, as
+/// `[line N] in name()` like clox does, followed by the file the frame's
+/// function was compiled from. Each line is counted in that file
+fn traceback(trace: &[TraceFrame], files: &Files) -> String {
+    let lines: Vec<String> = trace
+        .iter()
+        .map(|frame| {
+            let line = files
+                .line_index(frame.file, frame.span.start)
+                .map_or(0, |idx| idx + 1);
+            let function = match &frame.function {
+                Some(name) => format!("{name}()"),
+                None => "script".to_owned(),
+            };
+            match files.get(frame.file) {
+                Ok(file) => format!("[line {line}] in {function} at {}", file.name()),
+                Err(_) => format!("[line {line}] in {function}"),
+            }
+        })
+        .collect();
+    format!("stack trace:\n{}", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceback_resolves_every_frame_in_its_own_file() {
+        let mut files = Files::new();
+        let define = files.add(
+            "define.lox".to_owned(),
+            "fun g() {\n  return 1 + nil;\n}\n".to_owned(),
+        );
+        let call = files.add("call.lox".to_owned(), "g();\n".to_owned());
+        let trace = [
+            TraceFrame {
+                function: Some("g".into()),
+                file: define,
+                span: 21..22,
+            },
+            TraceFrame {
+                function: None,
+                file: call,
+                span: 0..3,
+            },
+        ];
+
+        assert_eq!(
+            traceback(&trace, &files),
+            "stack trace:\n[line 2] in g() at define.lox\n[line 1] in script at call.lox"
+        );
     }
 }
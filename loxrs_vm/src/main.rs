@@ -1,12 +1,16 @@
+mod bytecode;
 mod compiler;
 mod config;
 mod constants;
 mod entities;
 mod error;
+mod heap;
 mod input;
+mod optimizer;
 mod parser;
 mod scanner;
 mod types;
+mod verifier;
 mod vm;
 
 use input::read_input as start;
@@ -0,0 +1,627 @@
+//! Peephole optimizer, run over every finished chunk unless the opt level is `0`.
+//!
+//! The chunk is decoded into instructions, with jumps pointing at the instruction
+//! they land on rather than at a byte offset. Each instruction is then appended to
+//! the output in turn, rewriting the tail of the output for as long as a rule
+//! applies, which lets folds cascade: `1 + 2 * 3` becomes `1 6 ADD` and then `7`.
+//!
+//! A rewrite never spans a jump target other than its first instruction, since a
+//! jump into the middle of the rewritten instructions would have nowhere to land.
+
+use std::ops::Range;
+
+use crate::{
+    entities::{chunk::Chunk, opcode, value::Value},
+    error::{LoxError, OverflowError, Result},
+};
+
+type Span = Range<usize>;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Constant(Value),
+    /// `target` is the index of the instruction the jump lands on
+    Jump {
+        op: u8,
+        target: usize,
+    },
+    /// `ADD_CONSTANT`, which is written out as `CONSTANT ADD` again
+    /// when the constant doesn't fit in a one byte operand
+    AddConstant(Value),
+    /// `INCREMENT_LOCAL`, with the same fallback as [Op::AddConstant]
+    IncrementLocal {
+        slot: u8,
+        constant: Value,
+    },
+    Other {
+        op: u8,
+        operands: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Instruction {
+    op: Op,
+    span: Span,
+    is_target: bool,
+}
+
+impl Instruction {
+    fn opcode(&self) -> Option<u8> {
+        match &self.op {
+            Op::Other { op, .. } => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn is(&self, op: u8) -> bool {
+        self.opcode() == Some(op)
+    }
+
+    /// The first operand byte, which is the slot for `GET_LOCAL` and `SET_LOCAL`
+    fn slot(&self) -> Option<u8> {
+        match &self.op {
+            Op::Other { operands, .. } => operands.first().copied(),
+            _ => None,
+        }
+    }
+
+    fn constant(&self) -> Option<Value> {
+        match self.op {
+            Op::Constant(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction always leaves a boolean on top of the stack
+    fn produces_bool(&self) -> bool {
+        match self.op {
+            Op::Constant(val) => val.is_true() || val.is_false(),
+            Op::Other { op, .. } => matches!(
+                op,
+                opcode::NOT
+                    | opcode::EQUAL
+                    | opcode::GREATER
+                    | opcode::LESS
+                    | opcode::NOT_EQUAL
+                    | opcode::GREATER_EQUAL
+                    | opcode::LESS_EQUAL
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether execution can go on to the next instruction
+    fn falls_through(&self) -> bool {
+        match self.op {
+            Op::Jump { op, .. } => op != opcode::JUMP && op != opcode::LOOP,
+            _ => !self.is(opcode::RETURN),
+        }
+    }
+}
+
+pub fn optimize(chunk: &mut Chunk) -> Result<()> {
+    let mut instructions = fuse_conditional_pops(decode(chunk));
+    mark_targets(&mut instructions);
+
+    let mut out: Vec<Instruction> = Vec::with_capacity(instructions.len());
+    // where each decoded instruction ended up in `out`. Only jump targets are
+    // looked up, and those always keep their position
+    let mut moved_to = Vec::with_capacity(instructions.len());
+    for instruction in instructions {
+        moved_to.push(out.len());
+        out.push(instruction);
+        while rewrite(&mut out) {}
+    }
+
+    *chunk = encode(chunk, out, &moved_to)?;
+    Ok(())
+}
+
+fn decode(chunk: &Chunk) -> Vec<Instruction> {
+    let code = &chunk.code;
+    let mut starts = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        starts.push(offset);
+        let mut width =
+            opcode::operand_width(code[offset]).expect("the compiler only emits known opcodes");
+        if opcode::short_form(code[offset]) == opcode::CLOSURE {
+            let function = chunk.constants()[chunk.read_index(offset)];
+            let upvalue_count = function
+                .as_obj()
+                .and_then(|obj| obj.as_function())
+                .expect("closures are only ever built around functions")
+                .upvalue_count;
+            width += 2 * upvalue_count;
+        }
+        offset += 1 + width;
+    }
+
+    let index_of = |offset: usize| {
+        starts
+            .binary_search(&offset)
+            // a jump past the last instruction lands at the end of the code
+            .unwrap_or(starts.len())
+    };
+
+    starts
+        .iter()
+        .map(|&offset| {
+            let op = code[offset];
+            let next = offset + 1 + opcode::operand_width(op).unwrap_or_default();
+            let op = match op {
+                opcode::CONSTANT | opcode::CONSTANT_LONG => {
+                    Op::Constant(chunk.constants()[chunk.read_index(offset)])
+                }
+                opcode::JUMP | opcode::JUMP_IF_FALSE => Op::Jump {
+                    op,
+                    target: index_of(next + chunk.read_short(offset + 1) as usize),
+                },
+                opcode::LOOP => Op::Jump {
+                    op,
+                    target: index_of(next - chunk.read_short(offset + 1) as usize),
+                },
+                _ => {
+                    let end = starts
+                        .get(index_of(offset) + 1)
+                        .copied()
+                        .unwrap_or(code.len());
+                    Op::Other {
+                        op,
+                        operands: code[offset + 1..end].to_vec(),
+                    }
+                }
+            };
+            Instruction {
+                op,
+                span: chunk.span_at(offset),
+                is_target: false,
+            }
+        })
+        .collect()
+}
+
+/// `if`, `while` and `for` leave their condition on the stack when jumping, and pop
+/// it both right after the `JUMP_IF_FALSE` and where the jump lands. When nothing
+/// else reaches the landing `POP`, both make way for a `JUMP_IF_FALSE_POP`
+fn fuse_conditional_pops(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut landings = vec![0; instructions.len() + 1];
+    for instruction in &instructions {
+        if let Op::Jump { target, .. } = instruction.op {
+            landings[target] += 1;
+        }
+    }
+
+    let is_pop = |instructions: &[Instruction], idx: usize| {
+        instructions
+            .get(idx)
+            .is_some_and(|instruction| instruction.is(opcode::POP))
+    };
+    let mut removed = vec![false; instructions.len()];
+    for idx in 0..instructions.len() {
+        let Op::Jump {
+            op: opcode::JUMP_IF_FALSE,
+            target,
+        } = instructions[idx].op
+        else {
+            continue;
+        };
+        let fusable = target > idx + 1
+            && is_pop(&instructions, idx + 1)
+            && landings[idx + 1] == 0
+            && is_pop(&instructions, target)
+            && landings[target] == 1
+            && !instructions[target - 1].falls_through();
+        if fusable {
+            instructions[idx].op = Op::Jump {
+                op: opcode::JUMP_IF_FALSE_POP,
+                target,
+            };
+            removed[idx + 1] = true;
+            removed[target] = true;
+        }
+    }
+
+    // a jump to a removed instruction lands on the next one that's kept
+    let mut kept_before = Vec::with_capacity(instructions.len() + 1);
+    let mut kept = 0;
+    for is_removed in &removed {
+        kept_before.push(kept);
+        kept += usize::from(!is_removed);
+    }
+    kept_before.push(kept);
+
+    instructions
+        .into_iter()
+        .zip(removed)
+        .filter(|(_, is_removed)| !is_removed)
+        .map(|(mut instruction, _)| {
+            if let Op::Jump { target, .. } = &mut instruction.op {
+                *target = kept_before[*target];
+            }
+            instruction
+        })
+        .collect()
+}
+
+fn mark_targets(instructions: &mut [Instruction]) {
+    let targets: Vec<usize> = instructions
+        .iter()
+        .filter_map(|instruction| match instruction.op {
+            Op::Jump { target, .. } => Some(target),
+            _ => None,
+        })
+        .collect();
+    for target in targets {
+        if let Some(instruction) = instructions.get_mut(target) {
+            instruction.is_target = true;
+        }
+    }
+}
+
+/// Applies the first rule matching the tail of `out`, returning whether one did
+fn rewrite(out: &mut Vec<Instruction>) -> bool {
+    for n in [4, 3, 2] {
+        let Some(start) = out.len().checked_sub(n) else {
+            continue;
+        };
+        let window = &out[start..];
+        if window[1..].iter().any(|instruction| instruction.is_target) {
+            continue;
+        }
+        let Some((removed, replacement, kept)) = rule(window) else {
+            continue;
+        };
+
+        let span = window[kept].span.clone();
+        let start = out.len() - removed;
+        let is_target = out[start].is_target;
+        out.truncate(start);
+        out.extend(replacement.map(|op| Instruction {
+            op,
+            span,
+            is_target,
+        }));
+        return true;
+    }
+    false
+}
+
+/// Matches the instructions at the end of the output, returning how many
+/// of them to remove, what, if anything, to put in their place, and which
+/// instruction of the window lends it its span. That's the one that can still
+/// fail at runtime, so that the error points where it did before
+fn rule(window: &[Instruction]) -> Option<(usize, Option<Op>, usize)> {
+    match window {
+        // `local = local + constant;`
+        [get, add, set, pop]
+            if get.is(opcode::GET_LOCAL)
+                && set.is(opcode::SET_LOCAL)
+                && pop.is(opcode::POP)
+                && get.slot() == set.slot() =>
+        {
+            let Op::AddConstant(constant) = add.op else {
+                return None;
+            };
+            let slot = get.slot()?;
+            Some((4, Some(Op::IncrementLocal { slot, constant }), 1))
+        }
+        [_, _, _, _] => None,
+        [a, b, op] => {
+            let folded = a
+                .constant()
+                .zip(b.constant())
+                .zip(op.opcode())
+                .and_then(|((a, b), op)| fold_binary(op, a, b));
+            if let Some(val) = folded {
+                return Some((3, Some(Op::Constant(val)), 2));
+            }
+            // `!!` does nothing to a value that's already a boolean
+            let double_not = a.produces_bool() && b.is(opcode::NOT) && op.is(opcode::NOT);
+            double_not.then_some((2, None, 2))
+        }
+        [a, b] => {
+            let replacement = match (a.constant(), b.opcode()?) {
+                (Some(val), opcode::NEGATE) => Op::Constant(Value::from(-val.try_number().ok()?)),
+                (Some(val), opcode::NOT) => Op::Constant(Value::from(val.is_falsey())),
+                // tried after the three instruction window, so `1 + 2` still folds
+                (Some(val), opcode::ADD) => Op::AddConstant(val),
+                // the comparison can fail, while `NOT` can't
+                (None, opcode::NOT) => {
+                    let op = Op::Other {
+                        op: negated(a.opcode()?)?,
+                        operands: vec![],
+                    };
+                    return Some((2, Some(op), 0));
+                }
+                _ => return None,
+            };
+            Some((2, Some(replacement), 1))
+        }
+        _ => None,
+    }
+}
+
+/// The opcode computing `!(a op b)`
+fn negated(op: u8) -> Option<u8> {
+    match op {
+        opcode::LESS => Some(opcode::GREATER_EQUAL),
+        opcode::GREATER => Some(opcode::LESS_EQUAL),
+        opcode::EQUAL => Some(opcode::NOT_EQUAL),
+        // the fused opcodes are defined as negations, so this is exact
+        opcode::GREATER_EQUAL => Some(opcode::LESS),
+        opcode::LESS_EQUAL => Some(opcode::GREATER),
+        opcode::NOT_EQUAL => Some(opcode::EQUAL),
+        _ => None,
+    }
+}
+
+/// Computes `a op b` the same way the VM would, leaving anything that
+/// would be a runtime error or needs the heap to the VM
+fn fold_binary(op: u8, a: Value, b: Value) -> Option<Value> {
+    if op == opcode::EQUAL {
+        return Some(Value::from(a.equals(&b)));
+    }
+    if op == opcode::NOT_EQUAL {
+        return Some(Value::from(!a.equals(&b)));
+    }
+
+    let (a, b) = (a.try_number().ok()?, b.try_number().ok()?);
+    let val = match op {
+        opcode::ADD => Value::from(a + b),
+        opcode::SUBTRACT => Value::from(a - b),
+        opcode::MULTIPLY => Value::from(a * b),
+        opcode::DIVIDE => Value::from(a / b),
+        opcode::GREATER => Value::from(a > b),
+        opcode::LESS => Value::from(a < b),
+        opcode::GREATER_EQUAL => Value::from(!a.lt(&b)),
+        opcode::LESS_EQUAL => Value::from(!a.gt(&b)),
+        _ => return None,
+    };
+    Some(val)
+}
+
+/// Writes `instructions` into a new chunk, which starts out with the constant pool
+/// of `chunk` so that operands referring to constants keep their meaning
+fn encode(chunk: &Chunk, instructions: Vec<Instruction>, moved_to: &[usize]) -> Result<Chunk> {
+    let mut encoded = Chunk::from_parts(vec![], chunk.constants().to_vec(), []);
+    let mut starts = Vec::with_capacity(instructions.len());
+    let mut jumps = vec![];
+
+    for instruction in &instructions {
+        starts.push(encoded.code.len());
+        let span = &instruction.span;
+        match &instruction.op {
+            Op::Constant(val) => write_constant(&mut encoded, *val, span)?,
+            Op::Jump { op, target } => {
+                jumps.push((encoded.code.len(), *op, *target));
+                write(&mut encoded, *op, &[0xff, 0xff], span);
+            }
+            Op::AddConstant(val) => match encoded.add_short_constant(*val) {
+                Ok(idx) => write(&mut encoded, opcode::ADD_CONSTANT, &[idx], span),
+                Err(_) => {
+                    write_constant(&mut encoded, *val, span)?;
+                    write(&mut encoded, opcode::ADD, &[], span);
+                }
+            },
+            Op::IncrementLocal { slot, constant } => match encoded.add_short_constant(*constant) {
+                Ok(idx) => write(&mut encoded, opcode::INCREMENT_LOCAL, &[*slot, idx], span),
+                Err(_) => {
+                    write_get_local(&mut encoded, *slot, span);
+                    write_constant(&mut encoded, *constant, span)?;
+                    write(&mut encoded, opcode::ADD, &[], span);
+                    write(&mut encoded, opcode::SET_LOCAL, &[*slot], span);
+                    write(&mut encoded, opcode::POP, &[], span);
+                }
+            },
+            Op::Other {
+                op: opcode::GET_LOCAL,
+                operands,
+            } => write_get_local(&mut encoded, operands[0], span),
+            Op::Other { op, operands } => write(&mut encoded, *op, operands, span),
+        }
+    }
+
+    for (offset, op, target) in jumps {
+        let landing = moved_to
+            .get(target)
+            .and_then(|idx| starts.get(*idx))
+            .copied()
+            .unwrap_or(encoded.code.len());
+        let next = offset + 3;
+        let distance = match op {
+            opcode::LOOP => next - landing,
+            _ => landing - next,
+        };
+        // rewrites shrink the code unless a constant falls back to its long
+        // form, so a jump that fit before almost always fits now
+        let distance: u16 = distance.try_into().map_err(|_| {
+            <OverflowError as Into<LoxError>>::into(OverflowError::JumpTooLarge(u16::MAX as usize))
+        })?;
+        let [hi, lo] = distance.to_be_bytes();
+        encoded.code[offset + 1] = hi;
+        encoded.code[offset + 2] = lo;
+    }
+    Ok(encoded)
+}
+
+fn write(chunk: &mut Chunk, op: u8, operands: &[u8], span: &Span) {
+    chunk.write_chunk(op, span.clone());
+    for byte in operands {
+        chunk.write_chunk(*byte, span.clone());
+    }
+}
+
+fn write_constant(chunk: &mut Chunk, val: Value, span: &Span) -> Result<()> {
+    chunk
+        .add_constant(opcode::CONSTANT, val, span)
+        .map_err(|(err, _)| err)
+}
+
+/// The receiver and first few parameters live in the lowest slots,
+/// which get an opcode each so they don't need an operand
+fn write_get_local(chunk: &mut Chunk, slot: u8, span: &Span) {
+    match slot {
+        0..=3 => write(chunk, opcode::GET_LOCAL_0 + slot, &[], span),
+        _ => write(chunk, opcode::GET_LOCAL, &[slot], span),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::{compile, Mode},
+        entities::table::Table,
+        heap::Heap,
+    };
+
+    /// The script's disassembly at `opt_level`, without the span column
+    fn listing(source: &str, opt_level: u8) -> Vec<String> {
+        let mut heap = Heap::new();
+        let script = compile(
+            source,
+            0,
+            &mut heap,
+            &Table::default(),
+            opt_level,
+            Mode::File,
+        )
+        .unwrap();
+        let script = script.as_obj().and_then(|obj| obj.as_function()).unwrap();
+        script
+            .chunk
+            .to_string()
+            .lines()
+            .skip(1)
+            // the span is followed by the offset, right aligned in four columns
+            .map(|line| {
+                line[line.find(':').unwrap() - 4..]
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    /// Compiles `source` with and without the optimizer, comparing both
+    /// listings against what's expected of each
+    fn assert_optimizes(source: &str, unoptimized: &[&str], optimized: &[&str]) {
+        assert_eq!(listing(source, 0), unoptimized, "unoptimized");
+        assert_eq!(listing(source, 1), optimized, "optimized");
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_optimizes(
+            "print 1 + 2 * 3;",
+            &[
+                "0: OP_CONSTANT -> 1",
+                "2: OP_CONSTANT -> 2",
+                "4: OP_CONSTANT -> 3",
+                "6: OP_MULTIPLY",
+                "7: OP_ADD",
+                "8: OP_PRINT",
+                "9: OP_CONSTANT -> nil",
+                "11: OP_RETURN",
+            ],
+            &[
+                "0: OP_CONSTANT -> 7",
+                "2: OP_PRINT",
+                "3: OP_CONSTANT -> nil",
+                "5: OP_RETURN",
+            ],
+        );
+    }
+
+    #[test]
+    fn retargets_forward_jumps_over_removed_instructions() {
+        // both branches shrink, and the pops around them fuse into the jump
+        assert_optimizes(
+            "var a = 1; if (a < 2) { print -(1); } else { print !true; } print a;",
+            &[
+                "0: OP_CONSTANT -> 1",
+                "2: OP_DEFINE_GLOBAL -> a",
+                "4: OP_GET_GLOBAL -> a",
+                "6: OP_CONSTANT -> 2",
+                "8: OP_LESS",
+                "9: OP_JUMP_IF_FALSE 9 -> 20",
+                "12: OP_POP",
+                "13: OP_CONSTANT -> 1",
+                "15: OP_NEGATE",
+                "16: OP_PRINT",
+                "17: OP_JUMP 17 -> 25",
+                "20: OP_POP",
+                "21: OP_CONSTANT -> true",
+                "23: OP_NOT",
+                "24: OP_PRINT",
+                "25: OP_GET_GLOBAL -> a",
+                "27: OP_PRINT",
+                "28: OP_CONSTANT -> nil",
+                "30: OP_RETURN",
+            ],
+            &[
+                "0: OP_CONSTANT -> 1",
+                "2: OP_DEFINE_GLOBAL -> a",
+                "4: OP_GET_GLOBAL -> a",
+                "6: OP_CONSTANT -> 2",
+                "8: OP_LESS",
+                "9: OP_JUMP_IF_FALSE_POP 9 -> 18",
+                "12: OP_CONSTANT -> -1",
+                "14: OP_PRINT",
+                "15: OP_JUMP 15 -> 21",
+                "18: OP_CONSTANT -> false",
+                "20: OP_PRINT",
+                "21: OP_GET_GLOBAL -> a",
+                "23: OP_PRINT",
+                "24: OP_CONSTANT -> nil",
+                "26: OP_RETURN",
+            ],
+        );
+    }
+
+    #[test]
+    fn retargets_loops_over_removed_instructions() {
+        // the loop lands on the condition, which moves up as `GET_LOCAL 1` shrinks
+        // and `LESS NOT NOT` becomes `LESS`, while the body fuses into one instruction
+        assert_optimizes(
+            "{ var i = 0; while (!(i >= 3)) { i = i + 1; } print i; }",
+            &[
+                "0: OP_CONSTANT -> 0",
+                "2: OP_GET_LOCAL 1",
+                "4: OP_CONSTANT -> 3",
+                "6: OP_LESS",
+                "7: OP_NOT",
+                "8: OP_NOT",
+                "9: OP_JUMP_IF_FALSE 9 -> 24",
+                "12: OP_POP",
+                "13: OP_GET_LOCAL 1",
+                "15: OP_CONSTANT -> 1",
+                "17: OP_ADD",
+                "18: OP_SET_LOCAL 1",
+                "20: OP_POP",
+                "21: OP_LOOP 21 -> 2",
+                "24: OP_POP",
+                "25: OP_GET_LOCAL 1",
+                "27: OP_PRINT",
+                "28: OP_POP",
+                "29: OP_CONSTANT -> nil",
+                "31: OP_RETURN",
+            ],
+            &[
+                "0: OP_CONSTANT -> 0",
+                "2: OP_GET_LOCAL_1",
+                "3: OP_CONSTANT -> 3",
+                "5: OP_LESS",
+                "6: OP_JUMP_IF_FALSE_POP 6 -> 15",
+                "9: OP_INCREMENT_LOCAL 1 += 1",
+                "12: OP_LOOP 12 -> 2",
+                "15: OP_GET_LOCAL_1",
+                "16: OP_PRINT",
+                "17: OP_POP",
+                "18: OP_CONSTANT -> nil",
+                "20: OP_RETURN",
+            ],
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use std::mem;
+
 use log::{debug, trace};
 
 use crate::{
     constants::NO_SPAN,
     error::{InternalError, LoxErrorS, SyntaxError},
     scanner::{Token, TokenS},
+    types::Span,
 };
 
 pub struct Parser {
@@ -35,6 +38,34 @@ impl Parser {
         );
     }
 
+    pub fn is_at_end(&self) -> bool {
+        matches!(self.curr, None | Some((Token::EndOfFile, _)))
+    }
+
+    /// Compares only the token kind, so that e.g. any `Token::Literal`
+    /// matches regardless of its contents
+    pub fn check(&self, token: &Token) -> bool {
+        self.curr
+            .as_ref()
+            .is_some_and(|(curr, _)| mem::discriminant(curr) == mem::discriminant(token))
+    }
+
+    /// Like [Parser::check], but looks one token past `curr`
+    pub fn check_next(&self, token: &Token) -> bool {
+        self.tokens
+            .as_slice()
+            .first()
+            .is_some_and(|(next, _)| mem::discriminant(next) == mem::discriminant(token))
+    }
+
+    pub fn matches(&mut self, token: Token) -> bool {
+        if !self.check(&token) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
     pub fn consume(&mut self, token: Token, err: &str) -> Result<(), LoxErrorS> {
         if let Some(t) = &self.curr {
             if token == t.0 {
@@ -50,4 +81,17 @@ impl Parser {
 
         Err((InternalError::UnexpectedCodePath.into(), NO_SPAN))
     }
+
+    pub fn consume_identifier(&mut self, err: &str) -> Result<Span<String>, LoxErrorS> {
+        if let Some((Token::Literal(name), span)) = &self.curr {
+            let res = (name.clone(), span.clone());
+            self.advance();
+            return Ok(res);
+        }
+
+        Err((
+            SyntaxError::UnexpectedValue(err.to_owned()).into(),
+            self.curr.as_ref().map_or(NO_SPAN, |t| t.1.clone()),
+        ))
+    }
 }
@@ -13,7 +13,6 @@ use crate::{
 #[derive(Debug, Logos, PartialEq, Clone)]
 #[logos(error = ScannerError)]
 #[logos(skip r"[ \t]+")]
-
 pub enum Token {
     #[regex(r"[\n\r\f]", logos::skip)]
     Newline,
@@ -107,7 +106,7 @@ pub enum Token {
     Literal(String),
 
     // strings (to be defined separately from string blocks)
-    #[regex(r#""[^"\n]*""#, string)]
+    #[regex(r#""[^"]*""#, string)]
     String(String),
 
     // numbers
@@ -154,7 +153,7 @@ fn number(lexer: &mut logos::Lexer<Token>) -> Result<f64, ScannerError> {
     let slice = lexer.slice();
     slice
         .parse()
-        .or_else(|_| Err(ScannerError::InvalidNumber(slice.to_owned())))
+        .map_err(|_| ScannerError::InvalidNumber(slice.to_owned()))
 }
 
 fn multiline_comment(lex: &mut logos::Lexer<Token>) -> FilterResult<(), ScannerError> {
@@ -203,9 +202,9 @@ impl fmt::Display for Token {
             Token::EqualEqual => write!(f, "EqualEqual"),
             Token::LessEqual => write!(f, "LessEqual"),
             Token::GreaterEqual => write!(f, "GreaterEqual"),
-            Token::Literal(value) => write!(f, "{}: {:16}", "Literal", value),
-            Token::String(value) => write!(f, "{}: {:16}", "String", value),
-            Token::Number(value) => write!(f, "{}: {:16}", "Number", value),
+            Token::Literal(value) => write!(f, "Literal: {:16}", value),
+            Token::String(value) => write!(f, "String: {:16}", value),
+            Token::Number(value) => write!(f, "Number: {:16}", value),
             Token::And => write!(f, "and"),
             Token::Class => write!(f, "Class"),
             Token::Else => write!(f, "Else"),
@@ -287,7 +286,7 @@ pub fn scan(source: &str) -> Result<Vec<TokenS>, Vec<LoxErrorS>> {
         match el {
             Ok(token) => tokens.push(token),
             Err(err) => {
-                errs.push((err.0.into(), err.1).into());
+                errs.push((err.0.into(), err.1));
             }
         }
     }
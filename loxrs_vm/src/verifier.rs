@@ -0,0 +1,479 @@
+//! Checks a function's bytecode before the VM runs it, so that chunks loaded
+//! from a file or built by hand can't make the dispatch loop read out of bounds.
+//!
+//! Verification happens in two passes. The first decodes every instruction in
+//! order, checking that each opcode exists and that its operands fit in the chunk
+//! and point at constants of the right kind. The second follows every path through
+//! the code tracking how many values are on the stack, which must never drop below
+//! what an instruction pops and must agree wherever two paths meet.
+
+use crate::{
+    config::MAX_FUNCTION_DEPTH,
+    entities::{object::ObjFunction, opcode, value::Value},
+    error::{LoxErrorS, Result, VerifyError},
+};
+
+/// Verifies `function` and every function nested in its constant pool. The VM
+/// calls a script without arguments and wraps it in a closure with no upvalues
+pub fn verify(function: &ObjFunction) -> Result<(), LoxErrorS> {
+    if function.arity != 0 || function.upvalue_count != 0 {
+        let span = function.chunk.span_at(0);
+        return Err((VerifyError::InvalidScript.into(), span));
+    }
+    verify_nested(function, 1)
+}
+
+/// `depth` counts the script as 1, and is capped the same way the compiler
+/// and the loader cap it, so that hand built functions can't overflow the stack
+fn verify_nested(function: &ObjFunction, depth: usize) -> Result<(), LoxErrorS> {
+    let verifier = Verifier::new(function);
+    let starts = verifier.decode().map_err(|err| verifier.spanned(err))?;
+    verifier
+        .check_stack(&starts)
+        .map_err(|err| verifier.spanned(err))?;
+
+    for constant in function.chunk.constants() {
+        if let Some(nested) = as_function(constant) {
+            if depth >= MAX_FUNCTION_DEPTH {
+                return Err(verifier.spanned(VerifyError::TooDeep(MAX_FUNCTION_DEPTH)));
+            }
+            verify_nested(nested, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+fn as_function(value: &Value) -> Option<&ObjFunction> {
+    value.as_obj().and_then(|obj| obj.as_function())
+}
+
+/// A jump has to land on the start of an instruction inside the code
+fn jump_target(starts: &[bool], offset: usize, target: usize) -> Result<usize, VerifyError> {
+    match starts.get(target) {
+        Some(true) => Ok(target),
+        _ => Err(VerifyError::InvalidJump(offset)),
+    }
+}
+
+struct Verifier<'f> {
+    function: &'f ObjFunction,
+    code: &'f [u8],
+}
+
+impl<'f> Verifier<'f> {
+    fn new(function: &'f ObjFunction) -> Self {
+        Self {
+            function,
+            code: &function.chunk.code,
+        }
+    }
+
+    fn spanned(&self, err: VerifyError) -> LoxErrorS {
+        let span = self.function.chunk.span_at(err.offset());
+        (err.into(), span)
+    }
+
+    /// Returns whether an instruction starts at each offset of the code
+    fn decode(&self) -> Result<Vec<bool>, VerifyError> {
+        let mut starts = vec![false; self.code.len()];
+        let mut offset = 0;
+        while offset < self.code.len() {
+            starts[offset] = true;
+            offset += 1 + self.operands(offset)?;
+        }
+        Ok(starts)
+    }
+
+    /// Validates the operands of the instruction at `offset`, returning their width
+    fn operands(&self, offset: usize) -> Result<usize, VerifyError> {
+        let op = self.code[offset];
+        let mut width = opcode::operand_width(op).ok_or(VerifyError::UnknownOpcode(offset, op))?;
+        match opcode::short_form(op) {
+            opcode::CONSTANT => {
+                self.constant(offset, self.index(offset)?)?;
+            }
+            opcode::ADD_CONSTANT => {
+                self.constant(offset, self.byte(offset, 1)? as usize)?;
+            }
+            opcode::INCREMENT_LOCAL => {
+                self.constant(offset, self.byte(offset, 2)? as usize)?;
+            }
+            opcode::DEFINE_GLOBAL
+            | opcode::GET_GLOBAL
+            | opcode::SET_GLOBAL
+            | opcode::CLASS
+            | opcode::GET_PROPERTY
+            | opcode::SET_PROPERTY
+            | opcode::METHOD
+            | opcode::GET_SUPER
+            | opcode::INVOKE
+            | opcode::SUPER_INVOKE => self.identifier(offset)?,
+            // every upvalue is an `is_local` and an `index` byte
+            opcode::CLOSURE => width += 2 * self.closure_function(offset)?.upvalue_count,
+            _ => {}
+        }
+        if width > 0 {
+            self.byte(offset, width)?;
+        }
+        Ok(width)
+    }
+
+    /// The `nth` byte after the opcode at `offset`
+    fn byte(&self, offset: usize, nth: usize) -> Result<u8, VerifyError> {
+        self.code
+            .get(offset + nth)
+            .copied()
+            .ok_or(VerifyError::MissingOperands(offset))
+    }
+
+    fn constant(&self, offset: usize, idx: usize) -> Result<&'f Value, VerifyError> {
+        self.function
+            .chunk
+            .constants()
+            .get(idx)
+            .ok_or(VerifyError::InvalidConstant(offset, idx))
+    }
+
+    /// The constant index following the opcode at `offset`, which is 3 bytes
+    /// wide in long forms
+    fn index(&self, offset: usize) -> Result<usize, VerifyError> {
+        let idx = match opcode::index_width(self.code[offset]) {
+            1 => self.byte(offset, 1)? as u32,
+            _ => u32::from_be_bytes([
+                0,
+                self.byte(offset, 1)?,
+                self.byte(offset, 2)?,
+                self.byte(offset, 3)?,
+            ]),
+        };
+        Ok(idx as usize)
+    }
+
+    fn identifier(&self, offset: usize) -> Result<(), VerifyError> {
+        let constant = self.constant(offset, self.index(offset)?)?;
+        match constant.as_string() {
+            Some(_) => Ok(()),
+            None => Err(VerifyError::ExpectedIdentifier(offset)),
+        }
+    }
+
+    fn closure_function(&self, offset: usize) -> Result<&'f ObjFunction, VerifyError> {
+        let constant = self.constant(offset, self.index(offset)?)?;
+        as_function(constant).ok_or(VerifyError::ExpectedFunction(offset))
+    }
+
+    /// Follows every path from the function's entry, `starts` being the
+    /// instruction boundaries found by [Verifier::decode]
+    fn check_stack(&self, starts: &[bool]) -> Result<(), VerifyError> {
+        // the callee and its arguments are already on the stack on entry
+        let entry = self.function.arity + 1;
+        let mut depths: Vec<Option<usize>> = vec![None; self.code.len()];
+        let mut pending = vec![(0, entry)];
+
+        while let Some((offset, depth)) = pending.pop() {
+            // jump targets are checked before they're queued, so only
+            // falling through the last instruction can end up here
+            if offset >= self.code.len() {
+                return Err(VerifyError::RunsPastEnd(self.code.len()));
+            }
+            match depths[offset] {
+                Some(seen) if seen != depth => {
+                    return Err(VerifyError::InconsistentStack(offset, seen, depth))
+                }
+                Some(_) => continue,
+                None => depths[offset] = Some(depth),
+            }
+
+            let (pops, pushes) = self.stack_effect(offset, depth)?;
+            let depth = depth
+                .checked_sub(pops)
+                .ok_or(VerifyError::StackUnderflow(offset))?
+                + pushes;
+
+            let next = offset + 1 + self.operands(offset)?;
+            let jump = || self.short(offset);
+            match self.code[offset] {
+                opcode::RETURN => {}
+                opcode::JUMP => pending.push((jump_target(starts, offset, next + jump())?, depth)),
+                opcode::JUMP_IF_FALSE | opcode::JUMP_IF_FALSE_POP => {
+                    pending.push((next, depth));
+                    pending.push((jump_target(starts, offset, next + jump())?, depth));
+                }
+                opcode::LOOP => {
+                    let target = next
+                        .checked_sub(jump())
+                        .ok_or(VerifyError::InvalidJump(offset))?;
+                    pending.push((jump_target(starts, offset, target)?, depth));
+                }
+                _ => pending.push((next, depth)),
+            }
+        }
+        Ok(())
+    }
+
+    fn short(&self, offset: usize) -> usize {
+        self.function.chunk.read_short(offset + 1) as usize
+    }
+
+    /// How many values the instruction at `offset` pops and then pushes, given
+    /// `depth` values on the stack. Also checks that local and upvalue slots exist
+    fn stack_effect(&self, offset: usize, depth: usize) -> Result<(usize, usize), VerifyError> {
+        // the arguments of an invoke follow its constant index
+        let arg_count = || self.code[offset + 1 + opcode::index_width(self.code[offset])] as usize;
+        let effect = match opcode::short_form(self.code[offset]) {
+            opcode::CONSTANT | opcode::GET_GLOBAL | opcode::CLASS => (0, 1),
+            opcode::CLOSURE => {
+                self.check_captures(offset, depth)?;
+                (0, 1)
+            }
+            opcode::NEGATE
+            | opcode::NOT
+            | opcode::SET_GLOBAL
+            | opcode::JUMP_IF_FALSE
+            | opcode::GET_PROPERTY
+            | opcode::ADD_CONSTANT => (1, 1),
+            opcode::JUMP_IF_FALSE_POP => (1, 0),
+            opcode::ADD
+            | opcode::SUBTRACT
+            | opcode::MULTIPLY
+            | opcode::DIVIDE
+            | opcode::GREATER
+            | opcode::EQUAL
+            | opcode::LESS
+            | opcode::GREATER_EQUAL
+            | opcode::LESS_EQUAL
+            | opcode::NOT_EQUAL
+            | opcode::SET_PROPERTY
+            | opcode::GET_SUPER => (2, 1),
+            opcode::TERNARY_LOGICAL => (3, 1),
+            opcode::RETURN
+            | opcode::PRINT
+            | opcode::POP
+            | opcode::DEFINE_GLOBAL
+            | opcode::CLOSE_UPVALUE => (1, 0),
+            // both leave the class they add to on the stack
+            opcode::METHOD | opcode::INHERIT => (2, 1),
+            opcode::JUMP | opcode::LOOP => (0, 0),
+            opcode::GET_LOCAL | opcode::SET_LOCAL | opcode::INCREMENT_LOCAL => {
+                let slot = self.code[offset + 1];
+                self.check_local(offset, slot, depth)?;
+                match self.code[offset] {
+                    opcode::GET_LOCAL => (0, 1),
+                    opcode::SET_LOCAL => (1, 1),
+                    _ => (0, 0),
+                }
+            }
+            opcode::GET_LOCAL_0
+            | opcode::GET_LOCAL_1
+            | opcode::GET_LOCAL_2
+            | opcode::GET_LOCAL_3 => {
+                self.check_local(offset, self.code[offset] - opcode::GET_LOCAL_0, depth)?;
+                (0, 1)
+            }
+            opcode::GET_UPVALUE | opcode::SET_UPVALUE => {
+                let slot = self.code[offset + 1];
+                if slot as usize >= self.function.upvalue_count {
+                    return Err(VerifyError::InvalidUpvalue(offset, slot));
+                }
+                match self.code[offset] {
+                    opcode::GET_UPVALUE => (0, 1),
+                    _ => (1, 1),
+                }
+            }
+            // the callee or receiver, then the arguments
+            opcode::CALL => (self.code[offset + 1] as usize + 1, 1),
+            opcode::INVOKE => (arg_count() + 1, 1),
+            // the superclass sits on top of the arguments
+            opcode::SUPER_INVOKE => (arg_count() + 2, 1),
+            byte => return Err(VerifyError::UnknownOpcode(offset, byte)),
+        };
+        Ok(effect)
+    }
+
+    fn check_local(&self, offset: usize, slot: u8, depth: usize) -> Result<(), VerifyError> {
+        match (slot as usize) < depth {
+            true => Ok(()),
+            false => Err(VerifyError::InvalidLocal(offset, slot)),
+        }
+    }
+
+    /// Every upvalue a closure captures has to exist in the enclosing function,
+    /// either as a local slot or as one of its own upvalues
+    fn check_captures(&self, offset: usize, depth: usize) -> Result<(), VerifyError> {
+        let upvalue_count = self.closure_function(offset)?.upvalue_count;
+        let captures = offset + 1 + opcode::index_width(self.code[offset]);
+        for upvalue in 0..upvalue_count {
+            let is_local = self.code[captures + 2 * upvalue];
+            let index = self.code[captures + 1 + 2 * upvalue];
+            let valid = match is_local {
+                0 => (index as usize) < self.function.upvalue_count,
+                // a recursive local function captures the slot the closure is about to fill
+                1 => (index as usize) <= depth,
+                _ => false,
+            };
+            if !valid {
+                return Err(VerifyError::InvalidUpvalue(offset, index));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{chunk::Chunk, object::ObjKind},
+        error::LoxError,
+        heap::Heap,
+    };
+
+    fn function(code: Vec<u8>, constants: Vec<Value>) -> ObjFunction {
+        let mut function = ObjFunction::new(None);
+        function.chunk = Chunk::from_parts(code, constants, []);
+        function
+    }
+
+    fn verify_error(function: &ObjFunction) -> Option<VerifyError> {
+        match verify(function) {
+            Err((LoxError::VerifyError(err), _)) => Some(err),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_function() {
+        let code = vec![
+            opcode::CONSTANT,
+            0,
+            opcode::GET_LOCAL,
+            0,
+            opcode::POP,
+            opcode::RETURN,
+        ];
+        assert_eq!(verify_error(&function(code, vec![Value::NIL])), None);
+    }
+
+    #[test]
+    fn rejects_scripts_with_parameters_or_upvalues() {
+        let mut heap = Heap::new();
+        let mut inner = function(vec![opcode::GET_UPVALUE, 0, opcode::RETURN], vec![]);
+        inner.upvalue_count = 1;
+        let inner = heap.alloc(ObjKind::Function(inner));
+        // captures the script's own, nonexistent, first upvalue
+        let code = vec![opcode::CLOSURE, 0, 0, 0, opcode::POP, opcode::RETURN];
+        let mut script = function(code, vec![inner]);
+        script.upvalue_count = 1;
+        assert_eq!(verify_error(&script), Some(VerifyError::InvalidScript));
+
+        let mut script = function(vec![opcode::GET_LOCAL, 1, opcode::RETURN], vec![]);
+        script.arity = 1;
+        assert_eq!(verify_error(&script), Some(VerifyError::InvalidScript));
+    }
+
+    #[test]
+    fn rejects_operands_cut_off_by_the_end() {
+        let mut heap = Heap::new();
+        let constants = vec![Value::NIL, heap.intern("method")];
+        for code in [
+            vec![opcode::CONSTANT],
+            vec![opcode::JUMP, 0],
+            vec![opcode::CONSTANT_LONG, 0, 0],
+            // the argument count is missing
+            vec![opcode::INVOKE, 1],
+        ] {
+            assert_eq!(
+                verify_error(&function(code, constants.clone())),
+                Some(VerifyError::MissingOperands(0))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_jumps_outside_the_code_or_into_an_instruction() {
+        let constants = vec![Value::NIL];
+        for code in [
+            // past the end
+            vec![opcode::JUMP, 0, 10, opcode::CONSTANT, 0, opcode::RETURN],
+            // before the start
+            vec![opcode::LOOP, 0, 10, opcode::CONSTANT, 0, opcode::RETURN],
+            // onto the operand of `CONSTANT`
+            vec![opcode::JUMP, 0, 1, opcode::CONSTANT, 0, opcode::RETURN],
+        ] {
+            assert_eq!(
+                verify_error(&function(code, constants.clone())),
+                Some(VerifyError::InvalidJump(0))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_popping_an_empty_stack() {
+        // only the callee is on the stack on entry
+        let code = vec![opcode::POP, opcode::POP, opcode::RETURN];
+        assert_eq!(
+            verify_error(&function(code, vec![])),
+            Some(VerifyError::StackUnderflow(1))
+        );
+        let code = vec![opcode::ADD, opcode::RETURN];
+        assert_eq!(
+            verify_error(&function(code, vec![])),
+            Some(VerifyError::StackUnderflow(0))
+        );
+    }
+
+    #[test]
+    fn rejects_local_slots_past_the_stack() {
+        let code = vec![opcode::GET_LOCAL, 1, opcode::RETURN];
+        assert_eq!(
+            verify_error(&function(code, vec![])),
+            Some(VerifyError::InvalidLocal(0, 1))
+        );
+        let code = vec![opcode::GET_LOCAL_3, opcode::RETURN];
+        assert_eq!(
+            verify_error(&function(code, vec![])),
+            Some(VerifyError::InvalidLocal(0, 3))
+        );
+    }
+
+    #[test]
+    fn rejects_constants_past_the_pool() {
+        let constants = vec![Value::NIL];
+        for (code, idx) in [
+            (vec![opcode::CONSTANT, 1, opcode::RETURN], 1),
+            (
+                vec![opcode::CONSTANT_LONG, 1, 0, 0, opcode::RETURN],
+                1 << 16,
+            ),
+            (
+                vec![opcode::GET_GLOBAL_LONG, 0, 1, 0, opcode::RETURN],
+                1 << 8,
+            ),
+        ] {
+            assert_eq!(
+                verify_error(&function(code, constants.clone())),
+                Some(VerifyError::InvalidConstant(0, idx))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_functions_nested_too_deep() {
+        let mut heap = Heap::new();
+        let returning = |constant, heap: &mut Heap| {
+            let function = function(vec![opcode::CONSTANT, 0, opcode::RETURN], vec![constant]);
+            heap.alloc(ObjKind::Function(function))
+        };
+        let nested =
+            (1..MAX_FUNCTION_DEPTH).fold(Value::NIL, |constant, _| returning(constant, &mut heap));
+
+        let deepest = function(vec![opcode::CONSTANT, 0, opcode::RETURN], vec![nested]);
+        assert!(verify(&deepest).is_ok());
+        let deeper = returning(nested, &mut heap);
+        let deeper = function(vec![opcode::CONSTANT, 0, opcode::RETURN], vec![deeper]);
+        assert_eq!(
+            verify_error(&deeper),
+            Some(VerifyError::TooDeep(MAX_FUNCTION_DEPTH))
+        );
+    }
+}
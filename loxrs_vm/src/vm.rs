@@ -1,80 +1,536 @@
 use core::f64;
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    ops::Range,
+    ptr,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use arrayvec::ArrayVec;
+#[cfg(feature = "trace_execution")]
 use log::trace;
 
 use crate::{
-    compiler::compile,
-    config::MAX_STACK,
-    entities::{chunk::Chunk, opcode, value::Value},
-    error::{InternalError, InvalidAccessError, LoxError, LoxErrorS, Result},
+    bytecode,
+    compiler::{compile, Mode},
+    config::Config,
+    constants::NO_SPAN,
+    entities::{
+        chunk::Chunk,
+        object::{
+            NativeFn, Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjKind,
+            ObjNative, ObjUpvalue, UpvalueLocation,
+        },
+        opcode,
+        table::{StringKey, Table},
+        value::Value,
+    },
+    error::{
+        InternalError, InvalidAccessError, LoxError, LoxErrorS, OverflowError, Result, RuntimeError,
+    },
+    heap::Heap,
+    verifier,
 };
 
+/// A single function invocation. `slot_base` is the stack index of the
+/// called function, which every local slot is relative to
 #[derive(Debug)]
-pub struct VM {
-    chunk: Chunk,
-    stack: ArrayVec<Value, MAX_STACK>,
+struct CallFrame {
+    closure: *const ObjClosure,
+    /// the same closure as an object value, so the collector can reach it
+    callee: Value,
+    /// only up to date while the frame isn't executing, see [VM::save_ip]
     ip: usize,
+    slot_base: usize,
+}
+
+impl CallFrame {
+    fn closure(&self) -> &ObjClosure {
+        // SAFETY: closures live on the heap, which outlives every frame
+        unsafe { &*self.closure }
+    }
+
+    fn function(&self) -> &ObjFunction {
+        self.closure().function()
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.function().chunk
+    }
+}
+
+/// A frame of the stack trace a runtime error leaves behind
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// `None` for the script itself
+    pub function: Option<Rc<str>>,
+    /// the source file the function was compiled from, which `span` points into
+    pub file: usize,
+    /// the instruction the frame was running, i.e. the failing one for the
+    /// innermost frame and a call for every other one
+    pub span: Range<usize>,
+}
+
+#[derive(Debug)]
+pub struct VM {
+    config: Config,
+    frames: Vec<CallFrame>,
+    /// the frames the last runtime error unwound, innermost first
+    trace: Vec<TraceFrame>,
+    /// grows on demand, up to `config.stack_max`
+    stack: Vec<Value>,
+    globals: Table,
+    /// upvalues still pointing into the stack, sorted by stack slot
+    open_upvalues: Vec<Value>,
+    heap: Heap,
+    /// the executing frame's instruction pointer, chunk and `slot_base`, hoisted out
+    /// of `frames` by [VM::load_frame]. Dangling whenever `frames` is empty
+    ip: *const u8,
+    chunk: *const Chunk,
+    slot_base: usize,
 }
 
 impl VM {
-    pub fn new() -> Self {
-        Self {
-            chunk: Chunk::default(),
-            stack: ArrayVec::new(),
-            ip: 0,
-        }
+    pub fn new(config: Config) -> Self {
+        let mut vm = Self {
+            config,
+            frames: Vec::with_capacity(64),
+            trace: vec![],
+            stack: Vec::with_capacity(256),
+            globals: Table::default(),
+            open_upvalues: vec![],
+            heap: Heap::new(),
+            ip: ptr::null(),
+            chunk: ptr::null(),
+            slot_base: 0,
+        };
+        vm.define_native("clock", 0, clock);
+        vm.define_native("assert", 1, assert);
+        vm
     }
 
-    pub fn newline(&mut self) {
-        self.ip = 0
+    /// Drops what a failed run left behind, but not the globals or the heap,
+    /// so that the next run still sees everything the REPL defined so far
+    fn reset(&mut self) {
+        // closures that escaped into a global still need what they captured
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
     }
 
-    pub fn interpret(&mut self, source: &str) -> Result<(), Vec<LoxErrorS>> {
-        self.chunk = compile(source)?;
-        trace!("interpreting VM chunk: {}", self.chunk);
-        match self.run() {
-            Err(err) => Err(vec![(err, self.chunk.spans[self.ip].clone())]),
+    /// Compiles and runs `source`, which `file` identifies in the spans of
+    /// its functions, including in the stack trace of a runtime error
+    pub fn interpret(
+        &mut self,
+        source: &str,
+        file: usize,
+        mode: Mode,
+    ) -> Result<(), Vec<LoxErrorS>> {
+        let function = compile(
+            source,
+            file,
+            &mut self.heap,
+            &self.globals,
+            self.config.opt_level,
+            mode,
+        )?;
+        self.execute(function)
+    }
+
+    /// Compiles `source` into the bytecode file format, without running it. The
+    /// file only holds a single source, so loaded functions all point into file `0`
+    pub fn compile_to_bytes(&mut self, source: &str) -> Result<Vec<u8>, Vec<LoxErrorS>> {
+        let function = compile(
+            source,
+            0,
+            &mut self.heap,
+            &self.globals,
+            self.config.opt_level,
+            Mode::File,
+        )?;
+        let function = function
+            .as_obj()
+            .and_then(|obj| obj.as_function())
+            .expect("the compiler always produces a function");
+        Ok(bytecode::serialize(source, function))
+    }
+
+    /// Loads a script from the bytecode file format. Returns the source it was
+    /// compiled from, which the script's spans point into
+    pub fn load(&mut self, bytes: &[u8]) -> Result<(String, Value), LoxErrorS> {
+        bytecode::deserialize(bytes, &mut self.heap).map_err(|err| (err.into(), NO_SPAN))
+    }
+
+    /// Verifies `function` before running it as a script
+    pub fn execute(&mut self, function: Value) -> Result<(), Vec<LoxErrorS>> {
+        if let Some(function) = function.as_obj().and_then(|obj| obj.as_function()) {
+            verifier::verify(function).map_err(|err| vec![err])?;
+        }
+        // keeps the function reachable while its closure is allocated
+        self.stack.push(function);
+        let script = self.alloc(ObjKind::Closure(ObjClosure {
+            function,
+            upvalues: vec![],
+        }));
+        self.stack.pop();
+        self.stack.push(script);
+        match self.call_value(script, 0).and_then(|_| self.run()) {
+            Err(err) => {
+                self.save_ip();
+                // `ip` has already moved past the instruction's last byte
+                self.trace = self
+                    .frames
+                    .iter()
+                    .rev()
+                    .map(|frame| TraceFrame {
+                        function: frame.function().name.clone(),
+                        file: frame.function().file,
+                        span: frame.chunk().span_at(frame.ip.saturating_sub(1)),
+                    })
+                    .collect();
+                let span = self
+                    .trace
+                    .first()
+                    .map(|frame| frame.span.clone())
+                    .unwrap_or_default();
+                self.reset();
+                Err(vec![(err, span)])
+            }
             Ok(()) => Ok(()),
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    /// Hands over the stack trace of the last runtime error
+    pub fn take_trace(&mut self) -> Vec<TraceFrame> {
+        std::mem::take(&mut self.trace)
+    }
+
+    fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let key = self.intern(name);
+        // interned strings are only weakly held, so the name needs a root
+        self.stack.push(key);
+        let native = self.alloc(ObjKind::Native(ObjNative {
+            name: Rc::from(name),
+            arity,
+            function,
+        }));
+        self.stack.pop();
+
+        if let Ok(key) = StringKey::try_from(key) {
+            self.globals.insert(key, native);
+        }
+    }
+
+    /// Anything referenced by `kind` must already be reachable from a root,
+    /// since a collection may run before the new object exists
+    fn alloc(&mut self, kind: ObjKind) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(kind)
+    }
+
+    fn intern(&mut self, value: &str) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(value)
+    }
+
+    fn collect_garbage(&mut self) {
+        for value in &self.stack {
+            self.heap.mark_value(*value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_value(frame.callee);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_value(*upvalue);
+        }
+        self.heap.mark_table(&self.globals);
+        self.heap.collect();
+    }
+
+    fn frame(&self) -> Result<&CallFrame> {
+        self.frames
+            .last()
+            .ok_or_else(|| InternalError::UnexpectedCodePath.into())
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<()> {
+        match callee.as_obj().map(|obj| &obj.kind) {
+            Some(ObjKind::Closure(closure)) => self.call(callee, closure, arg_count),
+            Some(ObjKind::BoundMethod(bound)) => {
+                // the receiver takes the callee's slot, which is where `this` resolves to
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = bound.receiver;
+                self.call(bound.method, as_closure(&bound.method)?, arg_count)
+            }
+            Some(ObjKind::Class(class)) => {
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = self.alloc(ObjKind::Instance(ObjInstance::new(callee)));
+
+                let init = class
+                    .methods
+                    .borrow()
+                    .get(&self.heap.init_string())
+                    .copied();
+                match init {
+                    Some(init) => self.call(init, as_closure(&init)?, arg_count),
+                    None if arg_count != 0 => Err(RuntimeError::ArityMismatch(0, arg_count).into()),
+                    None => Ok(()),
+                }
+            }
+            Some(ObjKind::Native(native)) => {
+                if arg_count != native.arity {
+                    return Err(RuntimeError::ArityMismatch(native.arity, arg_count).into());
+                }
+
+                let args_start = self.stack.len() - arg_count;
+                let res = (native.function)(&self.stack[args_start..])?;
+                // drop the arguments along with the native function itself
+                self.stack.truncate(args_start - 1);
+                self.push(res)?;
+                Ok(())
+            }
+            _ => Err(RuntimeError::NotCallable.into()),
+        }
+    }
+
+    fn call(&mut self, callee: Value, closure: &ObjClosure, arg_count: usize) -> Result<()> {
+        let function = closure.function();
+        if arg_count != function.arity {
+            return Err(RuntimeError::ArityMismatch(function.arity, arg_count).into());
+        }
+
+        if self.frames.len() >= self.config.frames_max {
+            return Err(OverflowError::StackOverflow.into());
+        }
+
+        self.save_ip();
+        self.frames.push(CallFrame {
+            closure,
+            callee,
+            ip: 0,
+            slot_base: self.stack.len() - arg_count - 1,
+        });
+        self.load_frame();
+        Ok(())
+    }
+
+    fn push(&mut self, value: Value) -> Result<()> {
+        if self.stack.len() >= self.config.stack_max {
+            return Err(OverflowError::StackOverflow.into());
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Only ever runs verified code, which lets it read the code without bounds
+    /// checks: every operand is inside the chunk, every jump lands on an instruction
+    /// and no path runs past the end
+    fn run(&mut self) -> Result<()> {
         loop {
+            #[cfg(feature = "trace_execution")]
             trace!("chunk idx at: {self}");
-            let ip = self.ip();
-            match self.chunk.code[ip] {
-                opcode::RETURN => {
-                    let val = self.try_pop()?;
-
-                    println!("RETURN VALUE: {val}");
-                    break;
+            let op = self.read_byte();
+            // the only instruction that can stop the loop
+            if op == opcode::RETURN {
+                if self.return_from_call()? {
+                    return Ok(());
                 }
-                opcode::CONSTANT => self.constant()?,
-                opcode::NOT => self.not()?,
-                opcode::NEGATE => self.negate()?,
-                opcode::ADD => self.binary_op_number(|a, b| a + b)?,
-                opcode::SUBTRACT => self.binary_op_number(|a, b| a - b)?,
-                opcode::MULTIPLY => self.binary_op_number(|a, b| a * b)?,
-                opcode::DIVIDE => self.binary_op_number(|a, b| a / b)?,
-                opcode::GREATER => self.binary_op_number(|a, b| a > b)?,
-                opcode::LESS => self.binary_op_number(|a, b| a < b)?,
-                opcode::EQUAL => self.equal()?,
-                opcode::TERNARY_LOGICAL => self.ternary_op_number(|tern, a, b| match tern {
-                    true => a,
-                    false => b,
-                })?,
-                other => return Err(InternalError::UnknownOperation(other).into()),
+                continue;
             }
+            HANDLERS[op as usize](self)?;
         }
+    }
+
+    /// Returns whether it was the script itself that returned
+    fn return_from_call(&mut self) -> Result<bool> {
+        let res = self.try_pop()?;
+        let frame = self
+            .frames
+            .pop()
+            .ok_or::<LoxError>(InternalError::UnexpectedCodePath.into())?;
+        self.close_upvalues(frame.slot_base);
+        self.stack.truncate(frame.slot_base);
+
+        if self.frames.is_empty() {
+            return Ok(true);
+        }
+        self.load_frame();
+        self.push(res)?;
+        Ok(false)
+    }
+
+    /// Copies the state of the frame on top of `frames` into the VM,
+    /// where the dispatch loop reads it without going through `frames`
+    fn load_frame(&mut self) {
+        if let Some(frame) = self.frames.last() {
+            let chunk = frame.chunk();
+            // SAFETY: `frame.ip` is an offset into this same chunk's code
+            self.ip = unsafe { chunk.code.as_ptr().add(frame.ip) };
+            self.slot_base = frame.slot_base;
+            self.chunk = chunk;
+        }
+    }
+
+    /// Writes the instruction pointer back to the executing frame, which has to
+    /// happen before another frame is pushed and when an error stops the VM
+    fn save_ip(&mut self) {
+        if !self.frames.is_empty() {
+            let offset = self.offset();
+            if let Some(frame) = self.frames.last_mut() {
+                frame.ip = offset;
+            }
+        }
+    }
+
+    /// How far `ip` is into the executing chunk's code. Only valid while
+    /// there's a frame, as `ip` and `chunk` are left dangling otherwise
+    fn offset(&self) -> usize {
+        // SAFETY: both point into the executing chunk's code
+        unsafe { self.ip.offset_from(self.chunk().code.as_ptr()) as usize }
+    }
+
+    fn chunk(&self) -> &Chunk {
+        // SAFETY: the executing frame holds on to the closure owning the chunk
+        unsafe { &*self.chunk }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        // SAFETY: verified code never reads past the end of its chunk
+        unsafe {
+            let byte = *self.ip;
+            self.ip = self.ip.add(1);
+            byte
+        }
+    }
+
+    fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()])
+    }
+
+    fn jump(&mut self) -> Result<()> {
+        let offset = self.read_short() as usize;
+        // SAFETY: the verifier checked that every jump lands inside the code
+        self.ip = unsafe { self.ip.add(offset) };
+        Ok(())
+    }
+
+    fn jump_if_false(&mut self) -> Result<()> {
+        let offset = self.read_short() as usize;
+        if self.last_mut()?.is_falsey() {
+            // SAFETY: see `jump`
+            self.ip = unsafe { self.ip.add(offset) };
+        }
+        Ok(())
+    }
+
+    fn jump_if_false_pop(&mut self) -> Result<()> {
+        let offset = self.read_short() as usize;
+        if self.try_pop()?.is_falsey() {
+            // SAFETY: see `jump`
+            self.ip = unsafe { self.ip.add(offset) };
+        }
+        Ok(())
+    }
+
+    fn jump_back(&mut self) -> Result<()> {
+        let offset = self.read_short() as usize;
+        // SAFETY: see `jump`
+        self.ip = unsafe { self.ip.sub(offset) };
+        Ok(())
+    }
+
+    fn unknown_opcode(&mut self) -> Result<()> {
+        // SAFETY: the opcode was just read, so it's still inside the code
+        let op = unsafe { *self.ip.sub(1) };
+        Err(InternalError::UnknownOperation(op).into())
+    }
+
+    fn call_op(&mut self) -> Result<()> {
+        let arg_count = self.read_byte() as usize;
+        let callee = self.peek(arg_count)?;
+        self.call_value(callee, arg_count)
+    }
+
+    fn close_upvalue(&mut self) -> Result<()> {
+        self.close_upvalues(self.stack.len() - 1);
+        self.try_pop()?;
         Ok(())
     }
 
-    fn ip(&mut self) -> usize {
-        let ip = self.ip;
-        self.ip += 1;
-        ip
+    fn class<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let class = ObjClass::new(Rc::clone(&name.as_string().value));
+        let class = self.alloc(ObjKind::Class(class));
+        self.push(class)
+    }
+
+    fn get_super<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let superclass = self.try_pop()?;
+        self.bind_method(as_class(&superclass)?, name)
+    }
+
+    fn super_invoke<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let arg_count = self.read_byte() as usize;
+        let superclass = self.try_pop()?;
+        self.invoke_from_class(as_class(&superclass)?, name, arg_count)
+    }
+
+    fn print(&mut self) -> Result<()> {
+        let val = self.try_pop()?;
+        println!("{val}");
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<()> {
+        self.try_pop()?;
+        Ok(())
+    }
+
+    fn subtract(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| a - b)
+    }
+
+    fn multiply(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| a * b)
+    }
+
+    fn divide(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| a / b)
+    }
+
+    fn greater(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| a > b)
+    }
+
+    fn less(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| a < b)
+    }
+
+    fn greater_equal(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| !a.lt(&b))
+    }
+
+    fn less_equal(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| !a.gt(&b))
+    }
+
+    fn not_equal(&mut self) -> Result<()> {
+        self.equal()?;
+        self.not()
+    }
+
+    fn ternary_logical(&mut self) -> Result<()> {
+        self.ternary_op_number(|tern, a, b| match tern {
+            true => a,
+            false => b,
+        })
     }
 
     fn negate(&mut self) -> Result<(), LoxError> {
@@ -83,7 +539,7 @@ impl VM {
             *last = Value::from(num);
             Ok(())
         } else {
-            return Err(InvalidAccessError::StackEmpty.into());
+            Err(InvalidAccessError::StackEmpty.into())
         }
     }
 
@@ -92,24 +548,325 @@ impl VM {
             *last = Value::from(last.is_falsey());
             Ok(())
         } else {
-            return Err(InvalidAccessError::StackEmpty.into());
+            Err(InvalidAccessError::StackEmpty.into())
+        }
+    }
+
+    fn constant<const LONG: bool>(&mut self) -> Result<()> {
+        let val = self.read_const::<LONG>();
+        self.push(val)
+    }
+
+    /// Reads a constant index, 3 bytes wide in the long form of an opcode
+    fn read_const<const LONG: bool>(&mut self) -> Value {
+        let idx = match LONG {
+            true => u32::from_be_bytes([0, self.read_byte(), self.read_byte(), self.read_byte()]),
+            false => self.read_byte() as u32,
+        };
+        self.chunk().constants()[idx as usize]
+    }
+
+    fn read_identifier<const LONG: bool>(&mut self) -> Result<StringKey> {
+        self.read_const::<LONG>()
+            .try_into()
+            .map_err(|_| InternalError::UnexpectedCodePath.into())
+    }
+
+    fn define_global<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let val = self.try_pop()?;
+        self.globals.insert(name, val);
+        Ok(())
+    }
+
+    fn get_global<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        match self.globals.get(&name) {
+            Some(val) => {
+                self.push(*val)?;
+                Ok(())
+            }
+            None => Err(RuntimeError::UndefinedVariable(name.as_string().value.to_string()).into()),
         }
     }
 
-    fn constant(&mut self) -> Result<()> {
-        let val = self.read_const()?;
-        self.stack.push(val);
+    fn set_global<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let val = *self.last_mut()?;
+        match self.globals.get_mut(&name) {
+            // assignment is an expression, so the value stays on the stack
+            Some(global) => {
+                *global = val;
+                Ok(())
+            }
+            None => Err(RuntimeError::UndefinedVariable(name.as_string().value.to_string()).into()),
+        }
+    }
+
+    fn get_local(&mut self) -> Result<()> {
+        let slot = self.read_byte() as usize;
+        self.push_local(slot)
+    }
+
+    fn get_local_0(&mut self) -> Result<()> {
+        self.push_local(0)
+    }
+
+    fn get_local_1(&mut self) -> Result<()> {
+        self.push_local(1)
+    }
+
+    fn get_local_2(&mut self) -> Result<()> {
+        self.push_local(2)
+    }
+
+    fn get_local_3(&mut self) -> Result<()> {
+        self.push_local(3)
+    }
+
+    fn push_local(&mut self, slot: usize) -> Result<()> {
+        let slot = self.slot_base + slot;
+        let val = *self
+            .stack
+            .get(slot)
+            .ok_or::<LoxError>(InvalidAccessError::StackEmpty.into())?;
+        self.push(val)?;
         Ok(())
     }
 
-    fn read_const(&mut self) -> Result<Value> {
-        let ip = self.ip();
-        self.chunk.read_const(ip)
+    fn set_local(&mut self) -> Result<()> {
+        let slot = self.slot_base + self.read_byte() as usize;
+        let val = *self.last_mut()?;
+        let local = self
+            .stack
+            .get_mut(slot)
+            .ok_or::<LoxError>(InvalidAccessError::StackEmpty.into())?;
+        *local = val;
+        Ok(())
+    }
+
+    fn peek(&self, distance: usize) -> Result<Value> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .map(|idx| self.stack[idx])
+            .ok_or_else(|| InvalidAccessError::StackEmpty.into())
+    }
+
+    /// Fields shadow methods, so methods are only bound when no field matches
+    fn get_property<const LONG: bool>(&mut self) -> Result<()> {
+        let receiver = self.peek(0)?;
+        let instance = receiver
+            .as_obj()
+            .and_then(Obj::as_instance)
+            .ok_or::<LoxError>(RuntimeError::NotAnInstance.into())?;
+        let name = self.read_identifier::<LONG>()?;
+
+        let field = instance.fields.borrow().get(&name).copied();
+        if let Some(field) = field {
+            *self.last_mut()? = field;
+            return Ok(());
+        }
+
+        self.bind_method(instance.class(), name)
+    }
+
+    /// Replaces the receiver on top of the stack with `class`'s method bound to it
+    fn bind_method(&mut self, class: &ObjClass, name: StringKey) -> Result<()> {
+        let method = class.methods.borrow().get(&name).copied();
+        match method {
+            Some(method) => {
+                let receiver = self.peek(0)?;
+                let bound = self.alloc(ObjKind::BoundMethod(ObjBoundMethod { receiver, method }));
+                *self.last_mut()? = bound;
+                Ok(())
+            }
+            None => Err(undefined_property(&name)),
+        }
+    }
+
+    fn set_property<const LONG: bool>(&mut self) -> Result<()> {
+        let receiver = self.peek(1)?;
+        let instance = receiver
+            .as_obj()
+            .and_then(Obj::as_instance)
+            .ok_or::<LoxError>(RuntimeError::FieldOnNonInstance.into())?;
+        let name = self.read_identifier::<LONG>()?;
+
+        let val = self.try_pop()?;
+        instance.fields.borrow_mut().insert(name, val);
+        // assignment is an expression, so the value replaces the instance
+        *self.last_mut()? = val;
+        Ok(())
+    }
+
+    /// Copies every superclass method down into the subclass, so that
+    /// lookups never have to walk the inheritance chain at runtime
+    fn inherit(&mut self) -> Result<()> {
+        let superclass = self.peek(1)?;
+        let superclass = superclass
+            .as_obj()
+            .and_then(Obj::as_class)
+            .ok_or::<LoxError>(RuntimeError::SuperclassNotAClass.into())?;
+        let subclass = self.try_pop()?;
+
+        // collected first, as the two are the same class if the bytecode is corrupt
+        let methods: Vec<_> = superclass
+            .methods
+            .borrow()
+            .iter()
+            .map(|(name, method)| (*name, *method))
+            .collect();
+        as_class(&subclass)?.methods.borrow_mut().extend(methods);
+        Ok(())
+    }
+
+    fn method<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let method = self.try_pop()?;
+        let class = self.peek(0)?;
+        class
+            .as_obj()
+            .and_then(Obj::as_class)
+            .ok_or::<LoxError>(InternalError::UnexpectedCodePath.into())?
+            .methods
+            .borrow_mut()
+            .insert(name, method);
+        Ok(())
+    }
+
+    /// `receiver.name(args)` in a single instruction, without allocating a bound method
+    fn invoke<const LONG: bool>(&mut self) -> Result<()> {
+        let name = self.read_identifier::<LONG>()?;
+        let arg_count = self.read_byte() as usize;
+        let receiver = self.peek(arg_count)?;
+        let instance = receiver
+            .as_obj()
+            .and_then(Obj::as_instance)
+            .ok_or::<LoxError>(RuntimeError::MethodOnNonInstance.into())?;
+
+        // a field holding a function is called like any other value
+        let field = instance.fields.borrow().get(&name).copied();
+        if let Some(field) = field {
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = field;
+            return self.call_value(field, arg_count);
+        }
+
+        self.invoke_from_class(instance.class(), name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: &ObjClass,
+        name: StringKey,
+        arg_count: usize,
+    ) -> Result<()> {
+        let method = class.methods.borrow().get(&name).copied();
+        match method {
+            Some(method) => self.call(method, as_closure(&method)?, arg_count),
+            None => Err(undefined_property(&name)),
+        }
+    }
+
+    fn closure<const LONG: bool>(&mut self) -> Result<()> {
+        let function = self.read_const::<LONG>();
+        let upvalue_count = function
+            .as_obj()
+            .and_then(Obj::as_function)
+            .ok_or::<LoxError>(InternalError::UnexpectedCodePath.into())?
+            .upvalue_count;
+
+        let mut upvalues = Vec::with_capacity(upvalue_count);
+        for _ in 0..upvalue_count {
+            let is_local = self.read_byte() == 1;
+            let index = self.read_byte() as usize;
+            let upvalue = match is_local {
+                true => self.capture_upvalue(self.slot_base + index),
+                false => self.frame()?.closure().upvalues[index],
+            };
+            upvalues.push(upvalue);
+        }
+
+        let closure = self.alloc(ObjKind::Closure(ObjClosure { function, upvalues }));
+        self.push(closure)?;
+        Ok(())
+    }
+
+    /// Reuses the open upvalue for `slot` if there is one, so that
+    /// closures capturing the same variable share it
+    fn capture_upvalue(&mut self, slot: usize) -> Value {
+        let idx = self
+            .open_upvalues
+            .partition_point(|upvalue| open_slot(upvalue).is_some_and(|open| open < slot));
+        if let Some(upvalue) = self.open_upvalues.get(idx) {
+            if open_slot(upvalue) == Some(slot) {
+                return *upvalue;
+            }
+        }
+
+        let upvalue = self.alloc(ObjKind::Upvalue(ObjUpvalue {
+            location: UpvalueLocation::Open(slot).into(),
+        }));
+        self.open_upvalues.insert(idx, upvalue);
+        upvalue
+    }
+
+    /// Moves every variable at or above `last` off the stack and into its upvalue
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let Some(slot) = open_slot(upvalue).filter(|slot| *slot >= last) else {
+                break;
+            };
+            if let Some(upvalue) = upvalue.as_obj().and_then(Obj::as_upvalue) {
+                // only corrupt bytecode pops a captured slot without closing it first
+                let val = self.stack.get(slot).copied().unwrap_or(Value::NIL);
+                upvalue.location.set(UpvalueLocation::Closed(val));
+            }
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn read_upvalue(&mut self) -> Result<&ObjUpvalue> {
+        let idx = self.read_byte() as usize;
+        self.frame()?
+            .closure()
+            .upvalues
+            .get(idx)
+            .and_then(|upvalue| upvalue.as_obj())
+            .and_then(Obj::as_upvalue)
+            .ok_or_else(|| InternalError::UnexpectedCodePath.into())
+    }
+
+    fn get_upvalue(&mut self) -> Result<()> {
+        let val = match self.read_upvalue()?.location.get() {
+            UpvalueLocation::Open(slot) => *self.stack_slot(slot)?,
+            UpvalueLocation::Closed(val) => val,
+        };
+        self.push(val)?;
+        Ok(())
+    }
+
+    fn set_upvalue(&mut self) -> Result<()> {
+        // assignment is an expression, so the value stays on the stack
+        let val = *self.last_mut()?;
+        let location = &self.read_upvalue()?.location;
+        match location.get() {
+            UpvalueLocation::Open(slot) => *self.stack_slot(slot)? = val,
+            UpvalueLocation::Closed(_) => location.set(UpvalueLocation::Closed(val)),
+        }
+        Ok(())
+    }
+
+    fn stack_slot(&mut self, slot: usize) -> Result<&mut Value> {
+        self.stack
+            .get_mut(slot)
+            .ok_or_else(|| InvalidAccessError::StackEmpty.into())
     }
 
     fn try_pop(&mut self) -> Result<Value> {
         self.stack.pop().ok_or_else(|| {
-            <InvalidAccessError as Into<LoxError>>::into(InvalidAccessError::StackEmpty.into())
+            <InvalidAccessError as Into<LoxError>>::into(InvalidAccessError::StackEmpty)
         })
     }
 
@@ -122,7 +879,7 @@ impl VM {
             *a = Value::from(op(a.try_bool()?, b, c));
             Ok(())
         } else {
-            return Err(InvalidAccessError::StackEmpty.into());
+            Err(InvalidAccessError::StackEmpty.into())
         }
     }
 
@@ -130,16 +887,64 @@ impl VM {
         if let Some(a) = self.stack.last_mut() {
             Ok(a)
         } else {
-            return Err(InvalidAccessError::StackEmpty.into());
+            Err(InvalidAccessError::StackEmpty.into())
         }
     }
 
     fn equal(&mut self) -> Result<()> {
-        // TODO figure out if comparing stright u64s is enough in memory
         let b = self.try_pop()?;
         let a = self.last_mut()?;
 
-        *a = Value::from(*a == b);
+        *a = Value::from(a.equals(&b));
+        Ok(())
+    }
+
+    /// Adds a constant to the top of the stack, in place when both are numbers
+    fn add_constant(&mut self) -> Result<()> {
+        let b = self.read_const::<false>();
+        let a = self.last_mut()?;
+        if let (Ok(sum_a), Ok(sum_b)) = (a.try_number(), b.try_number()) {
+            *a = Value::from(sum_a + sum_b);
+            return Ok(());
+        }
+        self.push(b)?;
+        self.add()
+    }
+
+    /// `local = local + constant` as a statement, which leaves the stack untouched
+    fn increment_local(&mut self) -> Result<()> {
+        let slot = self.slot_base + self.read_byte() as usize;
+        let b = self.read_const::<false>();
+        let a = *self.stack_slot(slot)?;
+        let sum = match (a.try_number(), b.try_number()) {
+            (Ok(a), Ok(b)) => Value::from(a + b),
+            _ => {
+                self.push(a)?;
+                self.push(b)?;
+                self.add()?;
+                self.try_pop()?
+            }
+        };
+        *self.stack_slot(slot)? = sum;
+        Ok(())
+    }
+
+    /// `+` is overloaded for both number addition and string concatenation
+    fn add(&mut self) -> Result<()> {
+        let b = self.try_pop()?;
+        let a = self.try_pop()?;
+
+        let res = match (a.try_number(), b.try_number()) {
+            (Ok(a), Ok(b)) => Value::from(a + b),
+            _ => match (a.as_string(), b.as_string()) {
+                (Some(a), Some(b)) => {
+                    let concat = a.value.to_string() + &b.value;
+                    self.intern(&concat)
+                }
+                _ => return Err(RuntimeError::InvalidAddOperands.into()),
+            },
+        };
+        self.push(res)?;
         Ok(())
     }
 
@@ -157,7 +962,11 @@ impl VM {
 
 impl Display for VM {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        writeln!(f, "VM <pointer: {:04}>", self.ip)?;
+        let ip = match self.frames.is_empty() {
+            true => 0,
+            false => self.offset(),
+        };
+        writeln!(f, "VM <pointer: {ip:04}>")?;
         writeln!(f, "Stack:")?;
         for (i, value) in self.stack.iter().enumerate() {
             writeln!(f, "{i:04} [{:p}]: {value}", value)?; // Add a comma before the next element
@@ -165,3 +974,224 @@ impl Display for VM {
         Ok(())
     }
 }
+
+type Handler = fn(&mut VM) -> Result<()>;
+
+/// The handler for every opcode other than `RETURN`, indexed by the opcode
+static HANDLERS: [Handler; 256] = {
+    let mut handlers: [Handler; 256] = [VM::unknown_opcode; 256];
+    handlers[opcode::CALL as usize] = VM::call_op;
+    handlers[opcode::CLOSURE as usize] = VM::closure::<false>;
+    handlers[opcode::CLOSURE_LONG as usize] = VM::closure::<true>;
+    handlers[opcode::GET_UPVALUE as usize] = VM::get_upvalue;
+    handlers[opcode::SET_UPVALUE as usize] = VM::set_upvalue;
+    handlers[opcode::CLOSE_UPVALUE as usize] = VM::close_upvalue;
+    handlers[opcode::CLASS as usize] = VM::class::<false>;
+    handlers[opcode::CLASS_LONG as usize] = VM::class::<true>;
+    handlers[opcode::GET_PROPERTY as usize] = VM::get_property::<false>;
+    handlers[opcode::GET_PROPERTY_LONG as usize] = VM::get_property::<true>;
+    handlers[opcode::SET_PROPERTY as usize] = VM::set_property::<false>;
+    handlers[opcode::SET_PROPERTY_LONG as usize] = VM::set_property::<true>;
+    handlers[opcode::METHOD as usize] = VM::method::<false>;
+    handlers[opcode::METHOD_LONG as usize] = VM::method::<true>;
+    handlers[opcode::INVOKE as usize] = VM::invoke::<false>;
+    handlers[opcode::INVOKE_LONG as usize] = VM::invoke::<true>;
+    handlers[opcode::INHERIT as usize] = VM::inherit;
+    handlers[opcode::GET_SUPER as usize] = VM::get_super::<false>;
+    handlers[opcode::GET_SUPER_LONG as usize] = VM::get_super::<true>;
+    handlers[opcode::SUPER_INVOKE as usize] = VM::super_invoke::<false>;
+    handlers[opcode::SUPER_INVOKE_LONG as usize] = VM::super_invoke::<true>;
+    handlers[opcode::PRINT as usize] = VM::print;
+    handlers[opcode::POP as usize] = VM::pop;
+    handlers[opcode::DEFINE_GLOBAL as usize] = VM::define_global::<false>;
+    handlers[opcode::DEFINE_GLOBAL_LONG as usize] = VM::define_global::<true>;
+    handlers[opcode::GET_GLOBAL as usize] = VM::get_global::<false>;
+    handlers[opcode::GET_GLOBAL_LONG as usize] = VM::get_global::<true>;
+    handlers[opcode::SET_GLOBAL as usize] = VM::set_global::<false>;
+    handlers[opcode::SET_GLOBAL_LONG as usize] = VM::set_global::<true>;
+    handlers[opcode::GET_LOCAL as usize] = VM::get_local;
+    handlers[opcode::GET_LOCAL_0 as usize] = VM::get_local_0;
+    handlers[opcode::GET_LOCAL_1 as usize] = VM::get_local_1;
+    handlers[opcode::GET_LOCAL_2 as usize] = VM::get_local_2;
+    handlers[opcode::GET_LOCAL_3 as usize] = VM::get_local_3;
+    handlers[opcode::INCREMENT_LOCAL as usize] = VM::increment_local;
+    handlers[opcode::SET_LOCAL as usize] = VM::set_local;
+    handlers[opcode::JUMP as usize] = VM::jump;
+    handlers[opcode::JUMP_IF_FALSE as usize] = VM::jump_if_false;
+    handlers[opcode::JUMP_IF_FALSE_POP as usize] = VM::jump_if_false_pop;
+    handlers[opcode::LOOP as usize] = VM::jump_back;
+    handlers[opcode::CONSTANT as usize] = VM::constant::<false>;
+    handlers[opcode::CONSTANT_LONG as usize] = VM::constant::<true>;
+    handlers[opcode::NOT as usize] = VM::not;
+    handlers[opcode::NEGATE as usize] = VM::negate;
+    handlers[opcode::ADD as usize] = VM::add;
+    handlers[opcode::ADD_CONSTANT as usize] = VM::add_constant;
+    handlers[opcode::SUBTRACT as usize] = VM::subtract;
+    handlers[opcode::MULTIPLY as usize] = VM::multiply;
+    handlers[opcode::DIVIDE as usize] = VM::divide;
+    handlers[opcode::GREATER as usize] = VM::greater;
+    handlers[opcode::LESS as usize] = VM::less;
+    handlers[opcode::GREATER_EQUAL as usize] = VM::greater_equal;
+    handlers[opcode::LESS_EQUAL as usize] = VM::less_equal;
+    handlers[opcode::NOT_EQUAL as usize] = VM::not_equal;
+    handlers[opcode::EQUAL as usize] = VM::equal;
+    handlers[opcode::TERNARY_LOGICAL as usize] = VM::ternary_logical;
+    handlers
+};
+
+fn as_closure(value: &Value) -> Result<&ObjClosure> {
+    value
+        .as_obj()
+        .and_then(Obj::as_closure)
+        .ok_or_else(|| InternalError::UnexpectedCodePath.into())
+}
+
+fn as_class(value: &Value) -> Result<&ObjClass> {
+    value
+        .as_obj()
+        .and_then(Obj::as_class)
+        .ok_or_else(|| InternalError::UnexpectedCodePath.into())
+}
+
+fn undefined_property(name: &StringKey) -> LoxError {
+    RuntimeError::UndefinedProperty(name.as_string().value.to_string()).into()
+}
+
+fn open_slot(upvalue: &Value) -> Option<usize> {
+    match upvalue.as_obj()?.as_upvalue()?.location.get() {
+        UpvalueLocation::Open(slot) => Some(slot),
+        UpvalueLocation::Closed(_) => None,
+    }
+}
+
+fn clock(_args: &[Value]) -> Result<Value> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+    Ok(Value::from(now))
+}
+
+/// Fails unless its argument is `true`, the same as the tree-walker's `assert`
+fn assert(args: &[Value]) -> Result<Value> {
+    match args[0].is_true() {
+        true => Ok(args[0]),
+        false => Err(RuntimeError::AssertionFailed(args[0].to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enough globals, and properties in `Derived.get`, that both the script's
+    /// and the method's constant pool outgrow a single byte before the code
+    /// using every other opcode naming a constant
+    fn many_constants() -> String {
+        let mut source = String::new();
+        for n in 0..300 {
+            source += &format!("var v{n} = {n}.25;\n");
+        }
+        let properties: String = (0..300).map(|n| format!("this.p{n} = {n}.5;\n")).collect();
+        source += &format!(
+            "
+            v299 = v299 + v0;
+            fun outer() {{ var x = v1; fun inner() {{ return x; }} return inner; }}
+            var captured = outer()();
+            class Base {{ get() {{ return this.field; }} }}
+            class Derived < Base {{
+                init() {{ this.field = v299; }}
+                get() {{
+                    {properties}
+                    var method = super.get;
+                    return super.get() + method() + this.p299;
+                }}
+            }}
+            var instance = Derived();
+            instance.extra = 1;
+            var total = instance.get() + instance.extra;
+            "
+        );
+        source
+    }
+
+    fn global(vm: &mut VM, name: &str) -> Option<Value> {
+        let key = StringKey::try_from(vm.intern(name)).ok()?;
+        vm.globals.get(&key).copied()
+    }
+
+    #[test]
+    fn runtime_errors_point_into_the_file_defining_the_function() {
+        let mut vm = VM::new(Config::default());
+        let define = "fun g() { var unused = 0; return 1 + nil; }";
+        vm.interpret(define, 0, Mode::Repl).unwrap();
+        let errs = vm.interpret("g();", 1, Mode::Repl).unwrap_err();
+
+        let trace = vm.take_trace();
+        let failing = define.find("+").unwrap();
+        assert_eq!(errs[0].1, failing..failing + 1);
+        assert_eq!(trace[0].file, 0);
+        assert_eq!(trace[0].span, errs[0].1);
+        assert_eq!(trace[1].file, 1);
+    }
+
+    #[test]
+    fn optimized_code_fails_where_the_source_does() {
+        for (source, failing) in [
+            ("{ var i = \"a\"; i = i + 1; }", "+"),
+            ("print !(nil < 1);", "<"),
+        ] {
+            let failing = source.find(failing).unwrap();
+            for opt_level in [0, 1] {
+                let mut vm = VM::new(Config {
+                    opt_level,
+                    ..Config::default()
+                });
+                let errs = vm.interpret(source, 0, Mode::File).unwrap_err();
+                assert_eq!(errs[0].1, failing..failing + 1, "at opt level {opt_level}");
+            }
+        }
+    }
+
+    #[test]
+    fn assert_fails_unless_given_true() {
+        let mut vm = VM::new(Config::default());
+        vm.interpret("assert(1 == 1);", 0, Mode::File).unwrap();
+        let errs = vm.interpret("assert(1);", 0, Mode::File).unwrap_err();
+        assert!(matches!(
+            &errs[0].0,
+            LoxError::RuntimeError(RuntimeError::AssertionFailed(value)) if value == "1"
+        ));
+    }
+
+    #[test]
+    fn a_failed_line_closes_what_escaping_closures_captured() {
+        let mut vm = VM::new(Config::default());
+        let escape = "var f; { var x = \"captured\"; fun g() { return x; } f = g; nil + 1; }";
+        assert!(vm.interpret(escape, 0, Mode::Repl).is_err());
+        vm.interpret("var out = f();", 1, Mode::Repl).unwrap();
+
+        let captured = vm.intern("captured");
+        assert_eq!(global(&mut vm, "out"), Some(captured));
+    }
+
+    #[test]
+    fn runs_past_256_constants() {
+        let source = many_constants();
+        for opt_level in [0, 1] {
+            let mut vm = VM::new(Config {
+                opt_level,
+                ..Config::default()
+            });
+            vm.interpret(&source, 0, Mode::File)
+                .unwrap_or_else(|errs| panic!("failed at opt level {opt_level}: {errs:?}"));
+
+            assert_eq!(global(&mut vm, "v0"), Some(Value::from(0.25)));
+            assert_eq!(global(&mut vm, "v299"), Some(Value::from(299.5)));
+            assert_eq!(global(&mut vm, "captured"), Some(Value::from(1.25)));
+            assert_eq!(
+                global(&mut vm, "total"),
+                Some(Value::from(299.5 * 2.0 + 299.5 + 1.0))
+            );
+        }
+    }
+}